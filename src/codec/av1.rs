@@ -0,0 +1,224 @@
+use rav1e::prelude::*;
+
+use super::yuv::{self, I420};
+use super::{CodecId, EncoderSettings, FrameDecoder, FrameEncoder, Packet};
+use crate::recording::pixels::Frame;
+
+/**
+ * rav1e at its fastest preset with low latency on, so there's
+ * no frame reordering and nothing waits on frames that come later
+ *
+ * frames are cropped to an even size so the chroma planes line up
+ */
+pub struct Av1Encoder {
+    settings: EncoderSettings,
+
+    /**
+     * made on the first frame and again whenever the size changes
+     */
+    ctx: Option<Context<u8>>,
+    width: u32,
+    height: u32,
+
+    /**
+     * what the context was made with, rav1e doesn't take a new one in place
+     */
+    bitrate_kbps: u32,
+    force_keyframe: bool,
+
+    /**
+     * frames since the last keyframe, rav1e places its own
+     * but we need to know when one is coming
+     */
+    since_keyframe: u32,
+}
+
+impl Av1Encoder {
+    const SPEED_PRESET: u8 = 10;
+
+    pub fn new(settings: &EncoderSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            ctx: None,
+            width: 0,
+            height: 0,
+            bitrate_kbps: settings.bitrate_kbps,
+            force_keyframe: true,
+            since_keyframe: 0,
+        }
+    }
+
+    fn open(&mut self, width: u32, height: u32) {
+        let mut enc = EncoderConfig::with_speed_preset(Self::SPEED_PRESET);
+        enc.width = width as usize;
+        enc.height = height as usize;
+        enc.chroma_sampling = ChromaSampling::Cs420;
+        enc.time_base = Rational::new(1, self.settings.fps as u64);
+        enc.bitrate = (self.settings.bitrate_kbps * 1000) as i32;
+        enc.low_latency = true;
+        enc.min_key_frame_interval = 0;
+        enc.max_key_frame_interval = self.settings.gop as u64;
+
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let config = Config::new().with_encoder_config(enc).with_threads(threads);
+
+        self.ctx = Some(config.new_context().expect("failed creating AV1 encoder"));
+        self.width = width;
+        self.height = height;
+        self.bitrate_kbps = self.settings.bitrate_kbps;
+        self.force_keyframe = true;
+    }
+}
+
+impl FrameEncoder for Av1Encoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        let width = frame.width & !1;
+        let height = frame.height & !1;
+        let keyframe_due = self.force_keyframe || self.since_keyframe >= self.settings.gop;
+        if self.ctx.is_none()
+            || width != self.width
+            || height != self.height
+            || super::reopen_for_bitrate(
+                self.bitrate_kbps,
+                self.settings.bitrate_kbps,
+                keyframe_due,
+            )
+        {
+            self.open(width, height);
+        }
+        let ctx = self.ctx.as_mut().unwrap();
+
+        let yuv = I420::from_frame(frame, width, height);
+        let mut input = ctx.new_frame();
+        input.planes[0].copy_from_raw_u8(&yuv.y, yuv.width as usize, 1);
+        input.planes[1].copy_from_raw_u8(&yuv.u, yuv.chroma_width() as usize, 1);
+        input.planes[2].copy_from_raw_u8(&yuv.v, yuv.chroma_width() as usize, 1);
+
+        let params = if self.force_keyframe {
+            self.force_keyframe = false;
+            Some(FrameParameters {
+                frame_type_override: FrameTypeOverride::Key,
+                ..Default::default()
+            })
+        } else {
+            None
+        };
+
+        let mut keyframe = false;
+        let mut data = vec![];
+
+        if let Err(e) = ctx.send_frame((input, params)) {
+            println!("failed encoding AV1 frame: {:?}", e);
+            self.force_keyframe = true;
+        }
+
+        loop {
+            match ctx.receive_packet() {
+                Ok(packet) => {
+                    keyframe |= packet.frame_type == FrameType::KEY;
+                    data.extend_from_slice(&packet.data);
+                }
+                //  a frame got encoded but isn't ready to go out yet, ask again
+                Err(EncoderStatus::Encoded) => continue,
+                Err(EncoderStatus::NeedMoreData) => break,
+                Err(e) => {
+                    println!("failed encoding AV1 frame: {:?}", e);
+                    self.force_keyframe = true;
+                    break;
+                }
+            }
+        }
+
+        if keyframe {
+            self.since_keyframe = 0;
+        }
+        self.since_keyframe += 1;
+
+        Packet {
+            codec: CodecId::Av1,
+            keyframe,
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn force_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    fn set_bitrate(&mut self, kbps: u32) {
+        self.settings.bitrate_kbps = kbps;
+    }
+}
+
+pub struct Av1Decoder {
+    decoder: dav1d::Decoder,
+
+    /**
+     * false until we've seen a keyframe, anything before it
+     * refers to frames we never got
+     */
+    synced: bool,
+}
+
+impl Av1Decoder {
+    pub fn new() -> Self {
+        Self {
+            decoder: dav1d::Decoder::new().expect("failed creating AV1 decoder"),
+            synced: false,
+        }
+    }
+}
+
+impl FrameDecoder for Av1Decoder {
+    fn codec(&self) -> CodecId {
+        CodecId::Av1
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        use dav1d::PlanarImageComponent as Plane;
+
+        if packet.keyframe {
+            self.synced = true;
+        }
+        if !self.synced || packet.data.is_empty() {
+            return None;
+        }
+
+        if let Err(e) = self
+            .decoder
+            .send_data(packet.data.clone(), None, None, None)
+        {
+            println!("failed decoding AV1 frame: {:?}", e);
+            self.synced = false;
+            return None;
+        }
+
+        //  low latency streams have one picture per packet, keep the last one anyway
+        let mut frame = None;
+        while let Ok(picture) = self.decoder.get_picture() {
+            if picture.bit_depth() != 8 || picture.pixel_layout() != dav1d::PixelLayout::I420 {
+                println!("can't show {:?} AV1 frames", picture.pixel_layout());
+                continue;
+            }
+
+            let (y, u, v) = (
+                picture.plane(Plane::Y),
+                picture.plane(Plane::U),
+                picture.plane(Plane::V),
+            );
+            frame = Some(yuv::from_planes(
+                picture.width(),
+                picture.height(),
+                (&y, picture.stride(Plane::Y) as usize),
+                (&u, picture.stride(Plane::U) as usize),
+                (&v, picture.stride(Plane::V) as usize),
+            ));
+        }
+
+        frame
+    }
+}
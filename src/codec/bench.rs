@@ -0,0 +1,103 @@
+use std::time::{Duration, Instant};
+
+use super::{CodecId, Packet};
+use crate::config::HostConfig;
+use crate::recording::pixels::Frame;
+use crate::recording::source::{CaptureSource, SyntheticSource};
+
+/**
+ * `bench [<width>x<height>] [--frames n] [host options]...`
+ *
+ * times `--slice-codec` on whole frames against `CodecId::Slices`
+ * cutting the same frames into `--slices` pieces, encode and decode
+ */
+pub fn run(args: &[String]) {
+    let mut size = (2560, 1440);
+    let mut frames = 60;
+
+    //  the rest goes to `HostConfig`, which skips the first two
+    let mut host_args = vec![String::new(), String::new()];
+    let mut rest = args.iter().skip(2).peekable();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--frames" => {
+                frames = rest
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .expect("--frames needs a number")
+            }
+            _ => match arg.split_once('x').map(|(w, h)| (w.parse(), h.parse())) {
+                Some((Ok(width), Ok(height))) => size = (width, height),
+                _ => host_args.push(arg.clone()),
+            },
+        }
+    }
+
+    let config = HostConfig::from_args(&host_args);
+    let settings = config.encoder_settings();
+
+    let mut source = SyntheticSource::new(size.0, size.1);
+    let frames: Vec<Frame> = (0..frames).filter_map(|_| source.capture()).collect();
+    println!(
+        "{} {}x{} frames, {} vs {} slices of it on {} threads",
+        frames.len(),
+        size.0,
+        size.1,
+        settings.slice_codec.as_str(),
+        settings.slices,
+        rayon::current_num_threads()
+    );
+
+    let whole = measure(settings.slice_codec, &settings, &frames);
+    let sliced = measure(CodecId::Slices, &settings, &frames);
+
+    for (what, whole, sliced) in [
+        ("encode", whole.encode, sliced.encode),
+        ("decode", whole.decode, sliced.decode),
+    ] {
+        println!(
+            "{}: {:>8.2}ms whole, {:>8.2}ms sliced, {:.2}x",
+            what,
+            per_frame(whole, frames.len()),
+            per_frame(sliced, frames.len()),
+            whole.as_secs_f64() / sliced.as_secs_f64().max(1e-9)
+        );
+    }
+    println!(
+        "size:   {:>8} bytes whole, {:>8} bytes sliced",
+        whole.bytes / frames.len().max(1),
+        sliced.bytes / frames.len().max(1)
+    );
+}
+
+struct Timings {
+    encode: Duration,
+    decode: Duration,
+    bytes: usize,
+}
+
+fn measure(codec: CodecId, settings: &super::EncoderSettings, frames: &[Frame]) -> Timings {
+    let mut encoder = super::encoder(codec, settings);
+    let started = Instant::now();
+    let packets: Vec<Packet> = frames.iter().map(|f| encoder.encode(f)).collect();
+    let encode = started.elapsed();
+
+    let mut decoder = super::decoder(codec).expect("no decoder for the codec we benchmark");
+    let started = Instant::now();
+    for packet in &packets {
+        decoder
+            .decode(packet)
+            .expect("benchmark frame didn't decode");
+    }
+    let decode = started.elapsed();
+
+    Timings {
+        encode,
+        decode,
+        bytes: packets.iter().map(|p| p.data.len()).sum(),
+    }
+}
+
+fn per_frame(total: Duration, frames: usize) -> f64 {
+    total.as_secs_f64() * 1000.0 / frames.max(1) as f64
+}
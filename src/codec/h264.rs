@@ -0,0 +1,206 @@
+use openh264::decoder::Decoder;
+use openh264::encoder::{Encoder, EncoderConfig};
+use openh264::formats::YUVSource;
+
+use super::yuv::{self, I420};
+use super::{CodecId, EncoderSettings, FrameDecoder, FrameEncoder, Packet};
+use crate::recording::pixels::Frame;
+
+impl YUVSource for I420 {
+    fn width(&self) -> i32 {
+        self.width as i32
+    }
+
+    fn height(&self) -> i32 {
+        self.height as i32
+    }
+
+    fn y(&self) -> &[u8] {
+        &self.y
+    }
+
+    fn u(&self) -> &[u8] {
+        &self.u
+    }
+
+    fn v(&self) -> &[u8] {
+        &self.v
+    }
+
+    fn y_stride(&self) -> i32 {
+        self.width as i32
+    }
+
+    fn u_stride(&self) -> i32 {
+        self.chroma_width() as i32
+    }
+
+    fn v_stride(&self) -> i32 {
+        self.chroma_width() as i32
+    }
+}
+
+/**
+ * OpenH264 only does the constrained baseline profile, so there are
+ * no B-frames to wait for, every packet decodes as soon as it arrives
+ *
+ * frames are cropped to an even size, the encoder can't do odd ones
+ */
+pub struct H264Encoder {
+    settings: EncoderSettings,
+
+    /**
+     * made on the first frame and again whenever the size changes
+     */
+    encoder: Option<Encoder>,
+    width: u32,
+    height: u32,
+
+    /**
+     * what the encoder was made with, OpenH264 doesn't take a new one in place
+     */
+    bitrate_kbps: u32,
+
+    /**
+     * frames since the last keyframe
+     */
+    since_keyframe: u32,
+    force_keyframe: bool,
+}
+
+impl H264Encoder {
+    pub fn new(settings: &EncoderSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            encoder: None,
+            width: 0,
+            height: 0,
+            bitrate_kbps: settings.bitrate_kbps,
+            since_keyframe: 0,
+            force_keyframe: true,
+        }
+    }
+}
+
+impl FrameEncoder for H264Encoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        let width = frame.width & !1;
+        let height = frame.height & !1;
+
+        let keyframe_due = self.force_keyframe || self.since_keyframe >= self.settings.gop;
+        if self.encoder.is_none()
+            || width != self.width
+            || height != self.height
+            || super::reopen_for_bitrate(
+                self.bitrate_kbps,
+                self.settings.bitrate_kbps,
+                keyframe_due,
+            )
+        {
+            let config = EncoderConfig::new(width, height)
+                .set_bitrate_bps(self.settings.bitrate_kbps * 1000)
+                .max_frame_rate(self.settings.fps as f32);
+
+            self.encoder =
+                Some(Encoder::with_config(config).expect("failed creating H.264 encoder"));
+            self.width = width;
+            self.height = height;
+            self.bitrate_kbps = self.settings.bitrate_kbps;
+            self.force_keyframe = true;
+        }
+        let encoder = self.encoder.as_mut().unwrap();
+
+        let keyframe = self.force_keyframe || self.since_keyframe >= self.settings.gop;
+        if keyframe {
+            encoder.force_intra_frame();
+            self.since_keyframe = 0;
+            self.force_keyframe = false;
+        }
+        self.since_keyframe += 1;
+
+        let yuv = I420::from_frame(frame, width, height);
+        let data = match encoder.encode(&yuv) {
+            Ok(bitstream) => bitstream.to_vec(),
+            Err(e) => {
+                //  the next frame has to stand on its own then
+                println!("failed encoding H.264 frame: {}", e);
+                self.force_keyframe = true;
+                vec![]
+            }
+        };
+
+        Packet {
+            codec: CodecId::H264,
+            keyframe,
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn force_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    fn set_bitrate(&mut self, kbps: u32) {
+        self.settings.bitrate_kbps = kbps;
+    }
+}
+
+pub struct H264Decoder {
+    decoder: Decoder,
+
+    /**
+     * false until we've seen a keyframe, anything before it
+     * refers to frames we never got
+     */
+    synced: bool,
+}
+
+impl H264Decoder {
+    pub fn new() -> Self {
+        Self {
+            decoder: Decoder::new().expect("failed creating H.264 decoder"),
+            synced: false,
+        }
+    }
+}
+
+impl FrameDecoder for H264Decoder {
+    fn codec(&self) -> CodecId {
+        CodecId::H264
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        if packet.keyframe {
+            self.synced = true;
+        }
+        if !self.synced || packet.data.is_empty() {
+            return None;
+        }
+
+        let yuv = match self.decoder.decode(&packet.data) {
+            Ok(yuv) => yuv,
+            Err(e) => {
+                println!("failed decoding H.264 frame: {}", e);
+                self.synced = false;
+                return None;
+            }
+        };
+
+        let (width, height) = yuv.dimension_rgb();
+        if width == 0 || height == 0 {
+            //  nothing to show yet
+            return None;
+        }
+
+        let (y_stride, u_stride, v_stride) = yuv.strides_yuv();
+        Some(yuv::from_planes(
+            width as u32,
+            height as u32,
+            (yuv.y_with_stride(), y_stride),
+            (yuv.u_with_stride(), u_stride),
+            (yuv.v_with_stride(), v_stride),
+        ))
+    }
+}
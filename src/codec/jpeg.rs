@@ -0,0 +1,349 @@
+use std::time::{Duration, Instant};
+
+use super::{CodecId, EncoderSettings, FrameDecoder, FrameEncoder, Packet};
+use crate::recording::pixels::{Frame, PixelFormat};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Subsampling {
+    /**
+     * full resolution color, sharpest colored text
+     */
+    Yuv444,
+    Yuv422,
+    Yuv420,
+}
+
+impl Subsampling {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "444" => Some(Self::Yuv444),
+            "422" => Some(Self::Yuv422),
+            "420" => Some(Self::Yuv420),
+            _ => None,
+        }
+    }
+
+    /**
+     * horizontal and vertical sampling factors of the luma component,
+     * the chroma components are always 1x1
+     */
+    fn luma_factors(&self) -> (i32, i32) {
+        match self {
+            Self::Yuv444 => (1, 1),
+            Self::Yuv422 => (2, 1),
+            Self::Yuv420 => (2, 2),
+        }
+    }
+}
+
+/**
+ * the defaults are what mozjpeg does when left alone
+ */
+#[derive(Clone, Debug)]
+pub struct JpegSettings {
+    /**
+     * 1-100
+     */
+    pub quality: u8,
+    pub subsampling: Subsampling,
+    pub progressive: bool,
+
+    /**
+     * mozjpeg's trellis quantization, smaller files for a lot more encode time
+     */
+    pub trellis: bool,
+
+    /**
+     * lower the quality while encoding takes longer than a frame
+     * or the output goes over `EncoderSettings::bitrate_kbps`
+     */
+    pub adaptive: bool,
+}
+
+impl Default for JpegSettings {
+    fn default() -> Self {
+        Self {
+            quality: 75,
+            subsampling: Subsampling::Yuv420,
+            progressive: true,
+            trellis: true,
+            adaptive: false,
+        }
+    }
+}
+
+/**
+ * every frame on its own, every frame a keyframe
+ */
+pub struct JpegEncoder {
+    settings: JpegSettings,
+    adaptive: Option<AdaptiveQuality>,
+}
+
+impl JpegEncoder {
+    pub fn new(settings: &EncoderSettings) -> Self {
+        let jpeg = settings.jpeg.clone();
+
+        Self {
+            adaptive: if jpeg.adaptive {
+                Some(AdaptiveQuality::new(jpeg.quality, settings))
+            } else {
+                None
+            },
+            settings: jpeg,
+        }
+    }
+}
+
+impl FrameEncoder for JpegEncoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        let started = Instant::now();
+        let quality = match self.adaptive.as_ref() {
+            Some(adaptive) => adaptive.quality,
+            None => self.settings.quality,
+        };
+
+        //  libjpeg reads either packed layout itself, no need to convert
+        let frame = frame.packed();
+        let mut comp = mozjpeg::Compress::new(match frame.format {
+            PixelFormat::Rgba => mozjpeg::ColorSpace::JCS_EXT_RGBA,
+            _ => mozjpeg::ColorSpace::JCS_EXT_BGRX,
+        });
+
+        //  mozjpeg starts out with trellis and progressive on,
+        //  the fastest defaults are plain libjpeg without either
+        if !self.settings.trellis {
+            comp.set_fastest_defaults();
+        }
+        if self.settings.progressive {
+            comp.set_progressive_mode();
+        } else if self.settings.trellis {
+            //  drops the progressive scan script
+            comp.set_optimize_scans(false);
+        }
+
+        comp.set_quality(quality as f32);
+
+        let (h, v) = self.settings.subsampling.luma_factors();
+        for (i, component) in comp.components_mut().iter_mut().enumerate() {
+            component.h_samp_factor = if i == 0 { h } else { 1 };
+            component.v_samp_factor = if i == 0 { v } else { 1 };
+        }
+
+        comp.set_size(frame.width as usize, frame.height as usize);
+        comp.set_mem_dest();
+        comp.start_compress();
+
+        assert!(comp.write_scanlines(&frame.data[..]));
+
+        comp.finish_compress();
+        let data = comp.data_to_vec().unwrap();
+
+        if let Some(adaptive) = self.adaptive.as_mut() {
+            adaptive.update(started.elapsed(), data.len());
+        }
+
+        Packet {
+            codec: CodecId::Jpeg,
+            keyframe: true,
+            width: frame.width,
+            height: frame.height,
+            data,
+        }
+    }
+
+    /**
+     * only `adaptive` quality follows the bitrate, a fixed one
+     * stays what it was set to
+     */
+    fn set_bitrate(&mut self, kbps: u32) {
+        if let Some(adaptive) = self.adaptive.as_mut() {
+            adaptive.set_bitrate(kbps);
+        }
+    }
+}
+
+/**
+ * steps the quality down quickly when a frame goes over budget
+ * and back up slowly once there's room again
+ */
+struct AdaptiveQuality {
+    quality: u8,
+
+    /**
+     * never goes above what was configured
+     */
+    max_quality: u8,
+
+    /**
+     * a frame's worth of time at the target rate,
+     * times the threads taking turns with us
+     */
+    time_budget: Duration,
+    bytes_budget: f64,
+    fps: f64,
+
+    /**
+     * smoothed, one big frame shouldn't throw the quality off
+     */
+    encode_time: f64,
+    frame_bytes: f64,
+
+    /**
+     * frames in a row that were comfortably under budget
+     */
+    under_budget: u32,
+}
+
+impl AdaptiveQuality {
+    const MIN_QUALITY: u8 = 30;
+    const STEP_DOWN: u8 = 5;
+
+    /**
+     * how long it has to be quiet before we try a higher quality
+     */
+    const STEP_UP_AFTER: u32 = 30;
+
+    fn new(quality: u8, settings: &EncoderSettings) -> Self {
+        let fps = settings.fps.max(1) as f64;
+
+        Self {
+            quality,
+            max_quality: quality,
+            time_budget: Duration::from_secs_f64(settings.threads.max(1) as f64 / fps),
+            bytes_budget: settings.bitrate_kbps as f64 * 1000.0 / 8.0 / fps,
+            fps,
+            encode_time: 0.0,
+            frame_bytes: 0.0,
+            under_budget: 0,
+        }
+    }
+
+    fn set_bitrate(&mut self, kbps: u32) {
+        self.bytes_budget = kbps as f64 * 1000.0 / 8.0 / self.fps;
+    }
+
+    fn update(&mut self, encode_time: Duration, bytes: usize) {
+        self.encode_time = self.encode_time * 0.8 + encode_time.as_secs_f64() * 0.2;
+        self.frame_bytes = self.frame_bytes * 0.8 + bytes as f64 * 0.2;
+
+        let time = self.encode_time / self.time_budget.as_secs_f64();
+        let size = self.frame_bytes / self.bytes_budget;
+
+        if time > 1.0 || size > 1.0 {
+            self.under_budget = 0;
+            if self.quality > Self::MIN_QUALITY {
+                self.quality = self
+                    .quality
+                    .saturating_sub(Self::STEP_DOWN)
+                    .max(Self::MIN_QUALITY);
+                println!(
+                    "[H] jpeg quality down to {} ({:.0}% of time, {:.0}% of bandwidth)",
+                    self.quality,
+                    time * 100.0,
+                    size * 100.0
+                );
+            }
+        } else if time < 0.7 && size < 0.7 {
+            self.under_budget += 1;
+            if self.under_budget >= Self::STEP_UP_AFTER && self.quality < self.max_quality {
+                self.under_budget = 0;
+                self.quality += 1;
+            }
+        }
+    }
+}
+
+/**
+ * hands back `PixelFormat::Rgba`, what the client draws anyway
+ */
+pub struct JpegDecoder {}
+
+impl JpegDecoder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl FrameDecoder for JpegDecoder {
+    fn codec(&self) -> CodecId {
+        CodecId::Jpeg
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        let d = mozjpeg::Decompress::with_markers(mozjpeg::NO_MARKERS)
+            .from_mem(&packet.data)
+            .ok()?;
+        let mut rgba = d.rgba().ok()?;
+
+        let width = rgba.width() as u32;
+        let height = rgba.height() as u32;
+        let pixels: Vec<[u8; 4]> = rgba.read_scanlines()?;
+        rgba.finish_decompress();
+
+        Some(Frame {
+            data: pixels.concat(),
+            width,
+            height,
+            format: PixelFormat::Rgba,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HostConfig;
+
+    fn gradient() -> Frame {
+        let mut frame = Frame::blank(64, 64, PixelFormat::Bgrx);
+        for (i, p) in frame.data.chunks_exact_mut(4).enumerate() {
+            p.copy_from_slice(&[
+                (i % 64 * 4) as u8,
+                (i / 64 * 4) as u8,
+                (i % 7 * 30) as u8,
+                0xff,
+            ]);
+        }
+        frame
+    }
+
+    #[test]
+    fn fixed_quality_ignores_the_bitrate() {
+        let settings = HostConfig::default().encoder_settings();
+        let frame = gradient();
+        let expected = JpegEncoder::new(&settings).encode(&frame).data.len();
+
+        let mut encoder = JpegEncoder::new(&settings);
+        encoder.set_bitrate(1);
+        for _ in 0..10 {
+            assert_eq!(encoder.encode(&frame).data.len(), expected);
+        }
+        assert!(encoder.adaptive.is_none());
+    }
+
+    #[test]
+    fn adaptive_quality_follows_the_bitrate() {
+        let mut settings = HostConfig::default().encoder_settings();
+        settings.jpeg.adaptive = true;
+        let frame = gradient();
+
+        let mut encoder = JpegEncoder::new(&settings);
+        encoder.set_bitrate(1);
+        for _ in 0..10 {
+            encoder.encode(&frame);
+        }
+        assert!(encoder.adaptive.as_ref().unwrap().quality < settings.jpeg.quality);
+    }
+
+    #[test]
+    fn threads_share_the_time_budget() {
+        let settings = EncoderSettings {
+            fps: 30,
+            threads: 4,
+            ..HostConfig::default().encoder_settings()
+        };
+        let adaptive = AdaptiveQuality::new(80, &settings);
+        assert_eq!(adaptive.time_budget, Duration::from_secs_f64(4.0 / 30.0));
+    }
+}
@@ -0,0 +1,141 @@
+use super::{CodecId, FrameDecoder, FrameEncoder, Packet};
+use crate::recording::pixels::{Frame, PixelFormat};
+
+/**
+ * slower than QOI but smaller, and every client can open it
+ */
+pub struct PngEncoder {}
+
+impl PngEncoder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl FrameEncoder for PngEncoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        let rgba = frame.to(PixelFormat::Rgba);
+
+        let mut data = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut data, frame.width, frame.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_compression(png::Compression::Fast);
+            encoder.set_filter(png::FilterType::Sub);
+
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&rgba.data).unwrap();
+        }
+
+        Packet {
+            codec: CodecId::Png,
+            keyframe: true,
+            width: frame.width,
+            height: frame.height,
+            data,
+        }
+    }
+}
+
+pub struct PngDecoder {}
+
+impl PngDecoder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl FrameDecoder for PngDecoder {
+    fn codec(&self) -> CodecId {
+        CodecId::Png
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        let mut reader = png::Decoder::new(&packet.data[..]).read_info().ok()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).ok()?;
+        if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+            return None;
+        }
+
+        data.truncate(info.buffer_size());
+
+        Some(Frame {
+            data,
+            width: info.width,
+            height: info.height,
+            format: PixelFormat::Rgba,
+        })
+    }
+}
+
+/**
+ * the raw pixels through zstd at its fastest level,
+ * barely any work on either end so it's the one for fast LANs
+ *
+ * the packet data is the pixel format: u8, then the compressed pixels
+ */
+#[cfg(feature = "zstd")]
+pub struct ZstdEncoder {}
+
+#[cfg(feature = "zstd")]
+impl ZstdEncoder {
+    const LEVEL: i32 = 1;
+
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl FrameEncoder for ZstdEncoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        let mut data = vec![frame.format.as_u8()];
+        data.extend_from_slice(&zstd::bulk::compress(&frame.data, Self::LEVEL).unwrap());
+
+        Packet {
+            codec: CodecId::Zstd,
+            keyframe: true,
+            width: frame.width,
+            height: frame.height,
+            data,
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+pub struct ZstdDecoder {}
+
+#[cfg(feature = "zstd")]
+impl ZstdDecoder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl FrameDecoder for ZstdDecoder {
+    fn codec(&self) -> CodecId {
+        CodecId::Zstd
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        if !super::fits(packet.width, packet.height) {
+            return None;
+        }
+        let format = PixelFormat::from_u8(*packet.data.first()?)?;
+        let size = format.size(packet.width, packet.height);
+        let data = zstd::bulk::decompress(&packet.data[1..], size).ok()?;
+        if data.len() != size {
+            return None;
+        }
+
+        Some(Frame {
+            data,
+            width: packet.width,
+            height: packet.height,
+            format,
+        })
+    }
+}
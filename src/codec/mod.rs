@@ -0,0 +1,617 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::recording::pixels::Frame;
+
+#[cfg(feature = "av1")]
+pub mod av1;
+pub mod bench;
+#[cfg(feature = "h264")]
+pub mod h264;
+pub mod jpeg;
+pub mod lossless;
+pub mod qoi;
+pub mod slices;
+pub mod tiles;
+#[cfg(feature = "vpx")]
+pub mod vpx;
+#[cfg(any(feature = "h264", feature = "vpx", feature = "av1"))]
+pub mod yuv;
+
+/**
+ * which codec a packet was encoded with, the first byte of every packet
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CodecId {
+    Jpeg,
+    H264,
+    Vp8,
+    Vp9,
+    Av1,
+    Qoi,
+    Png,
+    Zstd,
+    Tiles,
+    Slices,
+    Hybrid,
+}
+
+impl CodecId {
+    pub const ALL: [CodecId; 11] = [
+        Self::Jpeg,
+        Self::H264,
+        Self::Vp8,
+        Self::Vp9,
+        Self::Av1,
+        Self::Qoi,
+        Self::Png,
+        Self::Zstd,
+        Self::Tiles,
+        Self::Slices,
+        Self::Hybrid,
+    ];
+
+    pub fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Jpeg),
+            2 => Some(Self::H264),
+            3 => Some(Self::Vp8),
+            4 => Some(Self::Vp9),
+            5 => Some(Self::Av1),
+            6 => Some(Self::Qoi),
+            7 => Some(Self::Png),
+            8 => Some(Self::Zstd),
+            9 => Some(Self::Tiles),
+            10 => Some(Self::Slices),
+            11 => Some(Self::Hybrid),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Self::Jpeg => 1,
+            Self::H264 => 2,
+            Self::Vp8 => 3,
+            Self::Vp9 => 4,
+            Self::Av1 => 5,
+            Self::Qoi => 6,
+            Self::Png => 7,
+            Self::Zstd => 8,
+            Self::Tiles => 9,
+            Self::Slices => 10,
+            Self::Hybrid => 11,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "jpeg" => Some(Self::Jpeg),
+            "h264" => Some(Self::H264),
+            "vp8" => Some(Self::Vp8),
+            "vp9" => Some(Self::Vp9),
+            "av1" => Some(Self::Av1),
+            "qoi" => Some(Self::Qoi),
+            "png" => Some(Self::Png),
+            "zstd" => Some(Self::Zstd),
+            "tiles" => Some(Self::Tiles),
+            "slices" => Some(Self::Slices),
+            "hybrid" => Some(Self::Hybrid),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::H264 => "h264",
+            Self::Vp8 => "vp8",
+            Self::Vp9 => "vp9",
+            Self::Av1 => "av1",
+            Self::Qoi => "qoi",
+            Self::Png => "png",
+            Self::Zstd => "zstd",
+            Self::Tiles => "tiles",
+            Self::Slices => "slices",
+            Self::Hybrid => "hybrid",
+        }
+    }
+
+    /**
+     * whether this build can encode and decode it,
+     * the video codecs are behind cargo features
+     */
+    pub fn is_available(&self) -> bool {
+        match self {
+            Self::Jpeg | Self::Qoi | Self::Png | Self::Tiles | Self::Slices | Self::Hybrid => true,
+            Self::H264 => cfg!(feature = "h264"),
+            Self::Vp8 | Self::Vp9 => cfg!(feature = "vpx"),
+            Self::Av1 => cfg!(feature = "av1"),
+            Self::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /**
+     * everything this build can decode, what a client offers the host
+     */
+    pub fn available() -> Vec<CodecId> {
+        Self::ALL
+            .iter()
+            .copied()
+            .filter(|c| c.is_available())
+            .collect()
+    }
+
+    /**
+     * `jpeg,vp9,...`, unknown names are left out
+     */
+    pub fn parse_list(names: &str) -> Vec<CodecId> {
+        names
+            .split(',')
+            .filter_map(|name| Self::parse(name.trim()))
+            .collect()
+    }
+
+    pub fn list_to_string(codecs: &[CodecId]) -> String {
+        codecs
+            .iter()
+            .map(|c| c.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /**
+     * the first of the host's `preferred` codecs the client can decode,
+     * JPEG if there's nothing in common since everybody has that
+     */
+    pub fn negotiate(preferred: &[CodecId], supported: &[CodecId]) -> CodecId {
+        preferred
+            .iter()
+            .copied()
+            .find(|c| supported.contains(c))
+            .unwrap_or(Self::Jpeg)
+    }
+
+    /**
+     * every packet stands on its own, so frames can be encoded in parallel
+     * and any of them can be dropped
+     */
+    pub fn is_intra_only(&self) -> bool {
+        match self {
+            Self::Jpeg | Self::Qoi | Self::Png | Self::Zstd | Self::Slices => true,
+            Self::H264 | Self::Vp8 | Self::Vp9 | Self::Av1 | Self::Tiles | Self::Hybrid => false,
+        }
+    }
+}
+
+/**
+ * knobs for the encoders, each one picks out what applies to it
+ */
+#[derive(Clone, Debug)]
+pub struct EncoderSettings {
+    /**
+     * what rate control aims for, adaptive JPEG keeps under it too
+     */
+    pub bitrate_kbps: u32,
+
+    /**
+     * frames from one keyframe to the next
+     */
+    pub gop: u32,
+
+    /**
+     * what rate control should plan for
+     */
+    pub fps: u32,
+    pub jpeg: jpeg::JpegSettings,
+
+    /**
+     * width and height of the tiles `CodecId::Tiles` splits frames into
+     */
+    pub tile_size: u32,
+
+    /**
+     * what each tile is encoded with, has to be intra-only
+     */
+    pub tile_codec: CodecId,
+
+    /**
+     * what `CodecId::Hybrid` sends text and UI tiles with,
+     * everything else goes with `tile_codec`
+     */
+    pub lossless_codec: CodecId,
+
+    /**
+     * how many pieces `CodecId::Slices` cuts frames into, and what they're encoded with
+     */
+    pub slices: u32,
+    pub slice_codec: CodecId,
+
+    /**
+     * encoder threads taking turns, each one only sees every Nth frame
+     */
+    pub threads: u32,
+}
+
+/**
+ * biggest width or height a decoder will take, anything past it is
+ * a broken packet, and allocating for it could take all our memory
+ */
+pub const MAX_DIMENSION: u32 = 16384;
+
+pub fn fits(width: u32, height: u32) -> bool {
+    width <= MAX_DIMENSION && height <= MAX_DIMENSION
+}
+
+/**
+ * bytes left after `reader`, counts and lengths read off the wire
+ * are checked against it before anything is allocated for them
+ */
+fn remaining(reader: &Cursor<&[u8]>) -> usize {
+    reader
+        .get_ref()
+        .len()
+        .saturating_sub(reader.position() as usize)
+}
+
+/**
+ * one encoded frame as it goes out on the wire
+ *
+ * codec: u8, flags: u8, width: u32, height: u32, then the encoded data,
+ * integers are little endian
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub codec: CodecId,
+
+    /**
+     * can be decoded without any of the packets before it
+     */
+    pub keyframe: bool,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+    pub const HEADER_LEN: usize = 10;
+
+    const KEYFRAME: u8 = 1;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::HEADER_LEN + self.data.len());
+        bytes.write_u8(self.codec.as_u8()).unwrap();
+        bytes
+            .write_u8(if self.keyframe { Self::KEYFRAME } else { 0 })
+            .unwrap();
+        bytes.write_u32::<LittleEndian>(self.width).unwrap();
+        bytes.write_u32::<LittleEndian>(self.height).unwrap();
+        bytes.write_all(&self.data).unwrap();
+
+        bytes
+    }
+
+    /**
+     * `None` if it's cut short, from a codec we've never heard of
+     * or bigger than `MAX_DIMENSION`
+     */
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(bytes);
+        let codec = CodecId::from_u8(reader.read_u8().ok()?)?;
+        let flags = reader.read_u8().ok()?;
+        let width = reader.read_u32::<LittleEndian>().ok()?;
+        let height = reader.read_u32::<LittleEndian>().ok()?;
+        if !fits(width, height) {
+            return None;
+        }
+
+        let mut data = vec![];
+        reader.read_to_end(&mut data).ok()?;
+
+        Some(Self {
+            codec,
+            keyframe: flags & Self::KEYFRAME != 0,
+            width,
+            height,
+            data,
+        })
+    }
+}
+
+/**
+ * turns captured frames into packets,
+ * one encoder sees every frame of the stream it's encoding in order
+ */
+pub trait FrameEncoder: Send {
+    fn encode(&mut self, frame: &Frame) -> Packet;
+
+    /**
+     * make the next packet a keyframe, because someone joined
+     * or some packet after the last one went missing
+     */
+    fn force_keyframe(&mut self) {}
+
+    /**
+     * congestion control moved the target, codecs without
+     * rate control just ignore it
+     */
+    fn set_bitrate(&mut self, _kbps: u32) {}
+}
+
+/**
+ * whether `FrameEncoder::set_bitrate` does anything for `codec`,
+ * lossless and fixed quality JPEG come out as big as they come out
+ */
+pub fn follows_bitrate(codec: CodecId, settings: &EncoderSettings) -> bool {
+    match codec {
+        CodecId::H264 | CodecId::Vp8 | CodecId::Vp9 | CodecId::Av1 => true,
+        CodecId::Jpeg => settings.jpeg.adaptive,
+        CodecId::Tiles | CodecId::Hybrid => follows_bitrate(settings.tile_codec, settings),
+        CodecId::Slices => follows_bitrate(settings.slice_codec, settings),
+        _ => false,
+    }
+}
+
+/**
+ * for encoders that can't change their rate in place and have to be made again,
+ * which costs a keyframe
+ *
+ * going up waits for the next keyframe that's due anyway, going down
+ * a lot can't, the link is already queueing up
+ */
+#[cfg_attr(not(any(feature = "h264", feature = "av1")), allow(dead_code))]
+pub fn reopen_for_bitrate(opened_kbps: u32, target_kbps: u32, keyframe_due: bool) -> bool {
+    if opened_kbps == target_kbps {
+        return false;
+    }
+
+    keyframe_due || target_kbps < opened_kbps / 4 * 3
+}
+
+/**
+ * turns packets back into frames, in whichever `PixelFormat`
+ * suits the codec, converting is up to whoever uses them
+ */
+pub trait FrameDecoder {
+    fn codec(&self) -> CodecId;
+
+    /**
+     * `None` if the packet is broken, or if the decoder
+     * is still waiting for a keyframe
+     */
+    fn decode(&mut self, packet: &Packet) -> Option<Frame>;
+}
+
+/**
+ * panics if the codec isn't `is_available`, check that when it's picked
+ */
+pub fn encoder(codec: CodecId, settings: &EncoderSettings) -> Box<dyn FrameEncoder> {
+    match codec {
+        CodecId::Jpeg => Box::new(jpeg::JpegEncoder::new(settings)),
+        CodecId::Qoi => Box::new(qoi::QoiEncoder::new()),
+        CodecId::Png => Box::new(lossless::PngEncoder::new()),
+        #[cfg(feature = "zstd")]
+        CodecId::Zstd => Box::new(lossless::ZstdEncoder::new()),
+        CodecId::Tiles => Box::new(tiles::TileEncoder::new(settings, false)),
+        CodecId::Hybrid => Box::new(tiles::TileEncoder::new(settings, true)),
+        CodecId::Slices => Box::new(slices::SliceEncoder::new(settings)),
+        #[cfg(feature = "h264")]
+        CodecId::H264 => Box::new(h264::H264Encoder::new(settings)),
+        #[cfg(feature = "vpx")]
+        CodecId::Vp8 | CodecId::Vp9 => Box::new(vpx::VpxEncoder::new(codec, settings)),
+        #[cfg(feature = "av1")]
+        CodecId::Av1 => Box::new(av1::Av1Encoder::new(settings)),
+        #[allow(unreachable_patterns)]
+        _ => panic!("this build can't encode {}", codec.as_str()),
+    }
+}
+
+pub fn decoder(codec: CodecId) -> Option<Box<dyn FrameDecoder>> {
+    match codec {
+        CodecId::Jpeg => Some(Box::new(jpeg::JpegDecoder::new())),
+        CodecId::Qoi => Some(Box::new(qoi::QoiDecoder::new())),
+        CodecId::Png => Some(Box::new(lossless::PngDecoder::new())),
+        CodecId::Slices => Some(Box::new(slices::SliceDecoder::new())),
+        #[cfg(feature = "zstd")]
+        CodecId::Zstd => Some(Box::new(lossless::ZstdDecoder::new())),
+        #[cfg(feature = "h264")]
+        CodecId::H264 => Some(Box::new(h264::H264Decoder::new())),
+        #[cfg(feature = "vpx")]
+        CodecId::Vp8 | CodecId::Vp9 => Some(Box::new(vpx::VpxDecoder::new(codec))),
+        #[cfg(feature = "av1")]
+        CodecId::Av1 => Some(Box::new(av1::Av1Decoder::new())),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/**
+ * decodes whatever comes in with the matching decoder,
+ * creating them as packets for each codec show up
+ */
+#[derive(Default)]
+pub struct Decoders {
+    decoders: HashMap<CodecId, Box<dyn FrameDecoder>>,
+}
+
+impl Decoders {
+    /**
+     * like `decode`, but also takes packets that only patch part of the picture
+     */
+    pub fn decode_update(&mut self, packet: &Packet) -> Option<tiles::Update> {
+        if packet.codec == CodecId::Tiles {
+            return tiles::decode(packet, self);
+        }
+
+        self.decode(packet).map(tiles::Update::Full)
+    }
+
+    pub fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        if !self.decoders.contains_key(&packet.codec) {
+            match decoder(packet.codec) {
+                Some(d) => {
+                    self.decoders.insert(d.codec(), d);
+                }
+                None => {
+                    println!("can't decode {} frames", packet.codec.as_str());
+                    return None;
+                }
+            }
+        }
+
+        self.decoders.get_mut(&packet.codec)?.decode(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HostConfig;
+    use crate::recording::pixels::PixelFormat;
+
+    /**
+     * B, G, R of the 16x16 blocks in the test frame, 4 across and 2 down
+     */
+    const COLOURS: [[u8; 3]; 8] = [
+        [0, 0, 0],
+        [255, 255, 255],
+        [0, 0, 255],
+        [0, 255, 0],
+        [255, 0, 0],
+        [0, 255, 255],
+        [255, 0, 255],
+        [128, 128, 128],
+    ];
+
+    fn golden_frame(format: PixelFormat) -> Frame {
+        let (width, height) = (64, 32);
+        let mut frame = Frame::blank(width, height, PixelFormat::Bgrx);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let [b, g, r] = COLOURS[y / 16 * 4 + x / 16];
+                let i = (y * width as usize + x) * 4;
+                frame.data[i..i + 4].copy_from_slice(&[b, g, r, 0xff]);
+            }
+        }
+
+        frame.into_format(format)
+    }
+
+    /**
+     * encodes the golden frame fed in as `format`, decodes it
+     * and checks the middle of every block is still its colour
+     */
+    fn round_trip(codec: CodecId, format: PixelFormat, tolerance: i32) {
+        let settings = HostConfig::default().encoder_settings();
+        let packet = encoder(codec, &settings).encode(&golden_frame(format));
+        let packet = Packet::from_bytes(&packet.to_bytes()).unwrap();
+
+        let mut retained = None;
+        let update = Decoders::default().decode_update(&packet).unwrap();
+        assert!(update.apply(&mut retained, PixelFormat::Bgrx));
+        let frame = retained.unwrap();
+        assert_eq!((frame.width, frame.height), (64, 32));
+
+        for (block, colour) in COLOURS.iter().enumerate() {
+            let (x, y) = (block % 4 * 16 + 8, block / 4 * 16 + 8);
+            let i = (y * 64 + x) * 4;
+            for c in 0..3 {
+                let got = frame.data[i + c] as i32;
+                assert!(
+                    (got - colour[c] as i32).abs() <= tolerance,
+                    "{} from {:?}: block {} is {:?}, should be {:?}",
+                    codec.as_str(),
+                    format,
+                    block,
+                    &frame.data[i..i + 3],
+                    colour
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn packets_survive_the_wire() {
+        let packet = Packet {
+            codec: CodecId::Tiles,
+            keyframe: true,
+            width: 1920,
+            height: 1080,
+            data: vec![1, 2, 3, 4, 5],
+        };
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), Packet::HEADER_LEN + 5);
+        assert_eq!(Packet::from_bytes(&bytes), Some(packet.clone()));
+
+        let empty = Packet {
+            keyframe: false,
+            data: vec![],
+            ..packet
+        };
+        assert_eq!(Packet::from_bytes(&empty.to_bytes()), Some(empty));
+    }
+
+    #[test]
+    fn broken_packets_are_rejected() {
+        let bytes = Packet {
+            codec: CodecId::Jpeg,
+            keyframe: false,
+            width: 64,
+            height: 32,
+            data: vec![9; 16],
+        }
+        .to_bytes();
+
+        for len in 0..Packet::HEADER_LEN {
+            assert_eq!(Packet::from_bytes(&bytes[..len]), None, "{} bytes", len);
+        }
+
+        let mut unknown = bytes.clone();
+        unknown[0] = 0xee;
+        assert_eq!(Packet::from_bytes(&unknown), None);
+
+        let mut huge = bytes;
+        huge[2..6].copy_from_slice(&(MAX_DIMENSION + 1).to_le_bytes());
+        assert_eq!(Packet::from_bytes(&huge), None);
+    }
+
+    #[test]
+    fn jpeg_keeps_colours() {
+        round_trip(CodecId::Jpeg, PixelFormat::Bgrx, 8);
+        round_trip(CodecId::Jpeg, PixelFormat::Rgba, 8);
+    }
+
+    #[test]
+    fn lossless_codecs_keep_colours_exactly() {
+        for codec in [CodecId::Qoi, CodecId::Png, CodecId::Zstd] {
+            if !codec.is_available() {
+                continue;
+            }
+            for format in [PixelFormat::Bgrx, PixelFormat::Rgba] {
+                round_trip(codec, format, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_and_slices_keep_colours() {
+        round_trip(CodecId::Tiles, PixelFormat::Bgrx, 8);
+        round_trip(CodecId::Slices, PixelFormat::Bgrx, 8);
+    }
+
+    #[test]
+    fn hybrid_sends_flat_colour_losslessly() {
+        round_trip(CodecId::Hybrid, PixelFormat::Bgrx, 0);
+    }
+
+    #[test]
+    fn video_codecs_keep_colours() {
+        for codec in [CodecId::H264, CodecId::Vp8, CodecId::Vp9, CodecId::Av1] {
+            if codec.is_available() {
+                round_trip(codec, PixelFormat::Bgrx, 24);
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use super::{CodecId, FrameDecoder, FrameEncoder, Packet};
+use crate::recording::pixels::{Frame, PixelFormat};
+
+//  https://qoiformat.org/qoi-specification.pdf
+const MAGIC: &[u8; 4] = b"qoif";
+const HEADER_LEN: usize = 14;
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const MASK: u8 = 0xc0;
+
+fn hash(p: [u8; 4]) -> usize {
+    (p[0] as usize * 3 + p[1] as usize * 5 + p[2] as usize * 7 + p[3] as usize * 11) % 64
+}
+
+/**
+ * lossless and cheap, runs of flat color and small gradients
+ * (UI, text, pixel art) shrink a lot, photos not so much
+ *
+ * packets are plain QOI images so they can be dumped and opened as they are
+ */
+pub struct QoiEncoder {}
+
+impl QoiEncoder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl FrameEncoder for QoiEncoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        Packet {
+            codec: CodecId::Qoi,
+            keyframe: true,
+            width: frame.width,
+            height: frame.height,
+            data: encode(frame),
+        }
+    }
+}
+
+pub fn encode(frame: &Frame) -> Vec<u8> {
+    let frame = frame.to(PixelFormat::Rgba);
+    let mut out = Vec::with_capacity(frame.data.len() / 4 + HEADER_LEN + END_MARKER.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&frame.width.to_be_bytes());
+    out.extend_from_slice(&frame.height.to_be_bytes());
+    //  4 channels, sRGB
+    out.extend_from_slice(&[4, 0]);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0u8, 0, 0, 255];
+    let mut run = 0u8;
+
+    let count = frame.data.len() / 4;
+    for (i, rgba) in frame.data.chunks_exact(4).enumerate() {
+        let px = [rgba[0], rgba[1], rgba[2], rgba[3]];
+
+        if px == prev {
+            run += 1;
+            if run == 62 || i == count - 1 {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+
+        if run > 0 {
+            out.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let h = hash(px);
+        if index[h] == px {
+            out.push(OP_INDEX | h as u8);
+        } else {
+            index[h] = px;
+
+            if px[3] == prev[3] {
+                let vr = px[0].wrapping_sub(prev[0]) as i8;
+                let vg = px[1].wrapping_sub(prev[1]) as i8;
+                let vb = px[2].wrapping_sub(prev[2]) as i8;
+                let vg_r = vr.wrapping_sub(vg);
+                let vg_b = vb.wrapping_sub(vg);
+
+                if (-2..=1).contains(&vr) && (-2..=1).contains(&vg) && (-2..=1).contains(&vb) {
+                    out.push(
+                        OP_DIFF | ((vr + 2) as u8) << 4 | ((vg + 2) as u8) << 2 | (vb + 2) as u8,
+                    );
+                } else if (-8..=7).contains(&vg_r)
+                    && (-32..=31).contains(&vg)
+                    && (-8..=7).contains(&vg_b)
+                {
+                    out.push(OP_LUMA | (vg + 32) as u8);
+                    out.push(((vg_r + 8) as u8) << 4 | (vg_b + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[OP_RGB, px[0], px[1], px[2]]);
+                }
+            } else {
+                out.extend_from_slice(&[OP_RGBA, px[0], px[1], px[2], px[3]]);
+            }
+        }
+
+        prev = px;
+    }
+
+    out.extend_from_slice(&END_MARKER);
+    out
+}
+
+/**
+ * longest run one op can stand for
+ */
+const MAX_RUN: usize = 62;
+
+/**
+ * `None` if it isn't a QOI image, it's cut short
+ * or it claims more pixels than it could hold
+ */
+pub fn decode(data: &[u8]) -> Option<Frame> {
+    if data.len() < HEADER_LEN || &data[0..4] != MAGIC {
+        return None;
+    }
+    let width = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let height = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+    let count = width as usize * height as usize;
+    if !super::fits(width, height) || count > (data.len() - HEADER_LEN) * MAX_RUN {
+        return None;
+    }
+
+    let mut frame = Frame {
+        data: Vec::with_capacity(count * 4),
+        width,
+        height,
+        format: PixelFormat::Rgba,
+    };
+
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0u8, 0, 0, 255];
+
+    let mut bytes = data[HEADER_LEN..].iter().copied();
+    let mut decoded = 0;
+    while decoded < count {
+        let op = bytes.next()?;
+
+        if op == OP_RGB {
+            px[0] = bytes.next()?;
+            px[1] = bytes.next()?;
+            px[2] = bytes.next()?;
+        } else if op == OP_RGBA {
+            px[0] = bytes.next()?;
+            px[1] = bytes.next()?;
+            px[2] = bytes.next()?;
+            px[3] = bytes.next()?;
+        } else {
+            match op & MASK {
+                OP_INDEX => px = index[op as usize],
+                OP_DIFF => {
+                    px[0] = px[0].wrapping_add((op >> 4 & 0x03).wrapping_sub(2));
+                    px[1] = px[1].wrapping_add((op >> 2 & 0x03).wrapping_sub(2));
+                    px[2] = px[2].wrapping_add((op & 0x03).wrapping_sub(2));
+                }
+                OP_LUMA => {
+                    let second = bytes.next()?;
+                    let vg = (op & 0x3f).wrapping_sub(32);
+                    px[0] = px[0].wrapping_add(vg.wrapping_sub(8).wrapping_add(second >> 4 & 0x0f));
+                    px[1] = px[1].wrapping_add(vg);
+                    px[2] = px[2].wrapping_add(vg.wrapping_sub(8).wrapping_add(second & 0x0f));
+                }
+                _ => {
+                    //  OP_RUN, the current pixel once more plus the run
+                    let run = (op & 0x3f) as usize + 1;
+                    for _ in 0..run.min(count - decoded) {
+                        frame.data.extend_from_slice(&px);
+                    }
+                    decoded += run;
+                    continue;
+                }
+            }
+        }
+
+        index[hash(px)] = px;
+        frame.data.extend_from_slice(&px);
+        decoded += 1;
+    }
+
+    Some(frame)
+}
+
+pub struct QoiDecoder {}
+
+impl QoiDecoder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl FrameDecoder for QoiDecoder {
+    fn codec(&self) -> CodecId {
+        CodecId::Qoi
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        decode(&packet.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::source::{CaptureSource, SyntheticSource};
+
+    /**
+     * flat runs, small steps, big jumps and alpha changes, so every op shows up
+     */
+    fn busy(width: u32, height: u32) -> Frame {
+        let mut frame = Frame::blank(width, height, PixelFormat::Rgba);
+        let mut seed = 7u32;
+        for (i, p) in frame.data.chunks_exact_mut(4).enumerate() {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let noise = (seed >> 16) as u8;
+            p.copy_from_slice(&match i % 97 {
+                0..=39 => [10, 20, 30, 255],
+                40..=69 => [i as u8, (i / 2) as u8, (i / 3) as u8, 255],
+                70..=89 => [noise, noise ^ 0x5a, noise.wrapping_mul(3), 255],
+                _ => [noise, 1, 2, noise],
+            });
+        }
+        frame
+    }
+
+    #[test]
+    fn round_trips_exactly() {
+        let frame = busy(61, 37);
+        let decoded = decode(&encode(&frame)).unwrap();
+        assert_eq!((decoded.width, decoded.height), (61, 37));
+        assert_eq!(decoded.data, frame.data);
+    }
+
+    #[test]
+    fn rejects_sizes_the_data_cant_hold() {
+        let mut data = encode(&busy(8, 8));
+        data[4..8].copy_from_slice(&60_000u32.to_be_bytes());
+        data[8..12].copy_from_slice(&60_000u32.to_be_bytes());
+        assert!(decode(&data).is_none());
+
+        let mut data = encode(&busy(8, 8));
+        data[8..12].copy_from_slice(&10_000u32.to_be_bytes());
+        assert!(decode(&data).is_none());
+    }
+
+    /**
+     * `cargo test --release -- --ignored`, debug builds are nowhere near
+     */
+    #[test]
+    #[ignore]
+    fn keeps_up_with_720p30() {
+        let frames: Vec<Frame> = {
+            let mut source = SyntheticSource::new(1280, 720);
+            (0..30).filter_map(|_| source.capture()).collect()
+        };
+
+        let started = std::time::Instant::now();
+        for frame in &frames {
+            decode(&encode(frame)).unwrap();
+        }
+        let per_frame = started.elapsed() / frames.len() as u32;
+        assert!(
+            per_frame < std::time::Duration::from_secs(1) / 30,
+            "{:?} per frame",
+            per_frame
+        );
+    }
+}
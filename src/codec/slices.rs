@@ -0,0 +1,202 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rayon::prelude::*;
+
+use super::{remaining, CodecId, EncoderSettings, FrameDecoder, FrameEncoder, Packet};
+use crate::recording::pixels::{Frame, PixelFormat};
+
+/**
+ * slices start on multiples of this, so JPEG's 16x16 blocks
+ * at 4:2:0 never straddle two of them
+ */
+const ALIGN: u32 = 16;
+
+/**
+ * where the horizontal slices of a `height` tall frame start and how tall they are
+ */
+fn bands(height: u32, count: u32) -> Vec<(u32, u32)> {
+    let per_slice = (height + count - 1) / count;
+    let per_slice = ((per_slice + ALIGN - 1) / ALIGN * ALIGN).max(ALIGN);
+
+    (0..height)
+        .step_by(per_slice as usize)
+        .map(|y| (y, per_slice.min(height - y)))
+        .collect()
+}
+
+/**
+ * cuts frames into horizontal slices and encodes them at the same time
+ * on rayon's pool, each with an intra-only codec of its own
+ *
+ * the packet data is inner codec: u8, slice count: u16,
+ * then for every slice y: u32, length: u32 and the slice's own packet
+ */
+pub struct SliceEncoder {
+    encoders: Vec<Box<dyn FrameEncoder>>,
+    inner_codec: CodecId,
+}
+
+impl SliceEncoder {
+    pub fn new(settings: &EncoderSettings) -> Self {
+        let count = settings.slices.max(1);
+
+        //  rate control sees one slice, it only gets its share
+        let mut settings = settings.clone();
+        settings.bitrate_kbps = (settings.bitrate_kbps / count).max(1);
+
+        Self {
+            encoders: (0..count)
+                .map(|_| super::encoder(settings.slice_codec, &settings))
+                .collect(),
+            inner_codec: settings.slice_codec,
+        }
+    }
+}
+
+impl FrameEncoder for SliceEncoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        let frame = &*frame.packed();
+        let bands = bands(frame.height, self.encoders.len() as u32);
+
+        let slices: Vec<(u32, Vec<u8>)> = self
+            .encoders
+            .par_iter_mut()
+            .zip(bands.par_iter())
+            .map(|(encoder, &(y, height))| {
+                let slice = frame.crop(0, y, frame.width, height);
+                (y, encoder.encode(&slice).to_bytes())
+            })
+            .collect();
+
+        let mut data = Vec::with_capacity(3 + slices.iter().map(|s| 8 + s.1.len()).sum::<usize>());
+        data.write_u8(self.inner_codec.as_u8()).unwrap();
+        data.write_u16::<LittleEndian>(slices.len() as u16).unwrap();
+        for (y, bytes) in slices {
+            data.write_u32::<LittleEndian>(y).unwrap();
+            data.write_u32::<LittleEndian>(bytes.len() as u32).unwrap();
+            data.extend_from_slice(&bytes);
+        }
+
+        Packet {
+            codec: CodecId::Slices,
+            keyframe: true,
+            width: frame.width,
+            height: frame.height,
+            data,
+        }
+    }
+
+    fn set_bitrate(&mut self, kbps: u32) {
+        let share = (kbps / self.encoders.len() as u32).max(1);
+        for encoder in &mut self.encoders {
+            encoder.set_bitrate(share);
+        }
+    }
+}
+
+/**
+ * decodes every slice at the same time and puts the frame back together
+ *
+ * the slice codecs are intra-only, so each task just makes its own decoder
+ */
+pub struct SliceDecoder {}
+
+impl SliceDecoder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl FrameDecoder for SliceDecoder {
+    fn codec(&self) -> CodecId {
+        CodecId::Slices
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        let mut reader = Cursor::new(&packet.data[..]);
+        let _inner = reader.read_u8().ok()?;
+        let count = reader.read_u16::<LittleEndian>().ok()?;
+        //  every slice has at least its y and length
+        if count as usize * 8 > remaining(&reader) {
+            return None;
+        }
+
+        let mut slices = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let y = reader.read_u32::<LittleEndian>().ok()?;
+            let len = reader.read_u32::<LittleEndian>().ok()?;
+            if len as usize > remaining(&reader) || y >= packet.height {
+                return None;
+            }
+
+            let mut bytes = vec![0; len as usize];
+            reader.read_exact(&mut bytes).ok()?;
+            slices.push((y, bytes));
+        }
+
+        let decoded: Option<Vec<(u32, Frame)>> = slices
+            .par_iter()
+            .map(|(y, bytes)| {
+                let packet = Packet::from_bytes(bytes)?;
+                if !packet.codec.is_intra_only() || packet.codec == CodecId::Slices {
+                    return None;
+                }
+
+                let frame = super::decoder(packet.codec)?.decode(&packet)?;
+                Some((*y, frame))
+            })
+            .collect();
+
+        //  a missing slice would leave a stripe of black, drop the whole frame
+        let decoded = decoded?;
+        let format = decoded
+            .first()
+            .map(|(_, slice)| slice.format)
+            .filter(|f| f.is_packed())
+            .unwrap_or(PixelFormat::Rgba);
+
+        let mut frame = Frame::blank(packet.width, packet.height, format);
+        for (y, slice) in decoded {
+            frame.blit(0, y, &slice);
+        }
+
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bands_are_aligned_and_cover_the_frame() {
+        for height in [1, 15, 16, 17, 100, 720, 1080, 1081] {
+            for count in [1, 2, 3, 4, 8, 16, 100] {
+                let bands = bands(height, count);
+                assert!(!bands.is_empty() && bands.len() <= count as usize);
+
+                let mut next = 0;
+                for &(y, h) in &bands {
+                    assert_eq!(y, next, "{} in {}", height, count);
+                    assert_eq!(y % ALIGN, 0);
+                    assert!(h > 0);
+                    next = y + h;
+                }
+                assert_eq!(next, height);
+            }
+        }
+    }
+
+    #[test]
+    fn slices_outside_the_frame_are_rejected() {
+        let settings = crate::config::HostConfig::default().encoder_settings();
+        let frame = Frame::blank(32, 64, PixelFormat::Bgrx);
+        let mut packet = SliceEncoder::new(&settings).encode(&frame);
+        assert!(SliceDecoder::new().decode(&packet).is_some());
+
+        //  the first slice's y
+        packet.data[3..7].copy_from_slice(&64u32.to_le_bytes());
+        assert!(SliceDecoder::new().decode(&packet).is_none());
+    }
+}
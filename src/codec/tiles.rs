@@ -0,0 +1,478 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{remaining, CodecId, Decoders, EncoderSettings, FrameEncoder, Packet, MAX_DIMENSION};
+use crate::recording::pixels::{Frame, PixelFormat};
+
+/**
+ * a changed part of the frame, already decoded
+ */
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub frame: Frame,
+}
+
+/**
+ * what a packet does to the picture the client is showing
+ */
+pub enum Update {
+    /**
+     * replaces it
+     */
+    Full(Frame),
+
+    /**
+     * patches it, unless `refresh` is set the rest stays what it was
+     */
+    Tiles {
+        width: u32,
+        height: u32,
+        refresh: bool,
+        tiles: Vec<Tile>,
+    },
+}
+
+impl Update {
+    /**
+     * brings `retained` up to date, false if this patches
+     * a frame we don't have and we need to wait for a refresh
+     *
+     * `retained` is kept in `format`, which has to be packed
+     */
+    pub fn apply(self, retained: &mut Option<Frame>, format: PixelFormat) -> bool {
+        match self {
+            Update::Full(frame) => {
+                *retained = Some(frame.into_format(format));
+                true
+            }
+            Update::Tiles {
+                width,
+                height,
+                refresh,
+                tiles,
+            } => {
+                let matches = retained
+                    .as_ref()
+                    .map(|r| r.width == width && r.height == height && r.format == format)
+                    .unwrap_or(false);
+                if refresh && !matches {
+                    *retained = Some(Frame::blank(width, height, format));
+                } else if !matches {
+                    return false;
+                }
+
+                let frame = retained.as_mut().unwrap();
+                for tile in tiles {
+                    frame.blit(tile.x, tile.y, &tile.frame);
+                }
+                true
+            }
+        }
+    }
+}
+
+/**
+ * splits frames into tiles and only sends the ones that changed
+ * since the last frame, each encoded on its own with an intra-only codec
+ *
+ * every `EncoderSettings::gop` frames all tiles go out again,
+ * so a client that lost a packet catches up
+ *
+ * for `CodecId::Hybrid` tiles that look like text or UI go out
+ * with a lossless codec instead, see `looks_synthetic`
+ *
+ * the packet data is inner codec: u8, tile size: u16, tile count: u32,
+ * then for every tile x: u32, y: u32, length: u32 and the tile's own packet,
+ * which says what it was encoded with
+ */
+pub struct TileEncoder {
+    inner: Box<dyn FrameEncoder>,
+    inner_codec: CodecId,
+
+    /**
+     * for the tiles `looks_synthetic` picks out, if we're hybrid
+     */
+    lossless: Option<Box<dyn FrameEncoder>>,
+    tile_size: u32,
+    refresh_every: u32,
+
+    /**
+     * what each tile looked like last time, row by row
+     */
+    hashes: Vec<u64>,
+    width: u32,
+    height: u32,
+
+    since_refresh: u32,
+    force_refresh: bool,
+
+    /**
+     * the whole frame's rate, what `inner` gets is a tile's share of it
+     */
+    kbps: Option<u32>,
+}
+
+impl TileEncoder {
+    pub fn new(settings: &EncoderSettings, hybrid: bool) -> Self {
+        Self {
+            inner: super::encoder(settings.tile_codec, settings),
+            inner_codec: settings.tile_codec,
+            lossless: if hybrid {
+                Some(super::encoder(settings.lossless_codec, settings))
+            } else {
+                None
+            },
+            //  it goes out as a u16
+            tile_size: settings.tile_size.clamp(8, MAX_DIMENSION),
+            refresh_every: settings.gop,
+            hashes: vec![],
+            width: 0,
+            height: 0,
+            since_refresh: 0,
+            force_refresh: true,
+            kbps: None,
+        }
+    }
+
+    /**
+     * `inner` sizes one tile at a time, so it gets the rate split over
+     * the whole grid, what a refresh sends, changed tiles stay under that
+     */
+    fn share_bitrate(&mut self) {
+        let kbps = match self.kbps {
+            Some(kbps) => kbps,
+            None => return,
+        };
+        let tiles = self.hashes.len().max(1) as u32;
+        self.inner.set_bitrate((kbps / tiles).max(1));
+    }
+
+    fn hash(frame: &Frame, x: u32, y: u32, width: u32, height: u32) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let row = width as usize * Frame::BYTES_PER_PIXEL;
+        for line in y..y + height {
+            let start = line as usize * frame.stride() + x as usize * Frame::BYTES_PER_PIXEL;
+            frame.data[start..start + row].hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+}
+
+impl FrameEncoder for TileEncoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        let frame = &*frame.packed();
+        let columns = (frame.width + self.tile_size - 1) / self.tile_size;
+        let rows = (frame.height + self.tile_size - 1) / self.tile_size;
+
+        if frame.width != self.width || frame.height != self.height {
+            self.width = frame.width;
+            self.height = frame.height;
+            self.hashes = vec![0; (columns * rows) as usize];
+            self.force_refresh = true;
+            self.share_bitrate();
+        }
+
+        let refresh = self.force_refresh || self.since_refresh >= self.refresh_every;
+        if refresh {
+            self.since_refresh = 0;
+            self.force_refresh = false;
+        }
+        self.since_refresh += 1;
+
+        let mut tiles = vec![];
+        let mut count = 0u32;
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = column * self.tile_size;
+                let y = row * self.tile_size;
+                let width = self.tile_size.min(frame.width - x);
+                let height = self.tile_size.min(frame.height - y);
+
+                let hash = Self::hash(frame, x, y, width, height);
+                let i = (row * columns + column) as usize;
+                if !refresh && self.hashes[i] == hash {
+                    continue;
+                }
+                self.hashes[i] = hash;
+
+                let tile = frame.crop(x, y, width, height);
+                let packet = match self.lossless.as_mut() {
+                    Some(lossless) if looks_synthetic(&tile) => lossless.encode(&tile),
+                    _ => self.inner.encode(&tile),
+                }
+                .to_bytes();
+                tiles.write_u32::<LittleEndian>(x).unwrap();
+                tiles.write_u32::<LittleEndian>(y).unwrap();
+                tiles
+                    .write_u32::<LittleEndian>(packet.len() as u32)
+                    .unwrap();
+                tiles.extend_from_slice(&packet);
+                count += 1;
+            }
+        }
+
+        let mut data = Vec::with_capacity(7 + tiles.len());
+        data.write_u8(self.inner_codec.as_u8()).unwrap();
+        data.write_u16::<LittleEndian>(self.tile_size as u16)
+            .unwrap();
+        data.write_u32::<LittleEndian>(count).unwrap();
+        data.extend_from_slice(&tiles);
+
+        Packet {
+            codec: CodecId::Tiles,
+            keyframe: refresh,
+            width: frame.width,
+            height: frame.height,
+            data,
+        }
+    }
+
+    fn force_keyframe(&mut self) {
+        self.force_refresh = true;
+    }
+
+    /**
+     * only the lossy tiles have a rate to follow
+     */
+    fn set_bitrate(&mut self, kbps: u32) {
+        self.kbps = Some(kbps);
+        self.share_bitrate();
+    }
+}
+
+/**
+ * past this many colours a tile counts as natural content
+ */
+const MAX_SYNTHETIC_COLOURS: usize = 32;
+
+/**
+ * text, UI and flat areas have few distinct colours and long stretches
+ * of the same one, photos and 3D scenes have neither
+ *
+ * the alpha byte isn't looked at, it's whatever X left there
+ */
+fn looks_synthetic(tile: &Frame) -> bool {
+    let rgb = |p: &[u8]| [p[0], p[1], p[2]];
+    let pixels = tile.data.chunks_exact(Frame::BYTES_PER_PIXEL);
+
+    let repeats = pixels
+        .clone()
+        .zip(pixels.clone().skip(1))
+        .filter(|(a, b)| rgb(a) == rgb(b))
+        .count();
+    if repeats * 2 >= pixels.len() {
+        return true;
+    }
+
+    let mut colours = Vec::with_capacity(MAX_SYNTHETIC_COLOURS + 1);
+    for pixel in pixels {
+        let colour = rgb(pixel);
+        if !colours.contains(&colour) {
+            colours.push(colour);
+            if colours.len() > MAX_SYNTHETIC_COLOURS {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/**
+ * `None` if the packet is cut short or a tile doesn't decode,
+ * a partial update would leave stale tiles behind
+ */
+pub fn decode(packet: &Packet, decoders: &mut Decoders) -> Option<Update> {
+    let mut reader = Cursor::new(&packet.data[..]);
+    let _inner = reader.read_u8().ok()?;
+    let _tile_size = reader.read_u16::<LittleEndian>().ok()?;
+    let count = reader.read_u32::<LittleEndian>().ok()?;
+    //  every tile has at least its x, y and length
+    if count as usize * 12 > remaining(&reader) {
+        return None;
+    }
+
+    let mut tiles = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let x = reader.read_u32::<LittleEndian>().ok()?;
+        let y = reader.read_u32::<LittleEndian>().ok()?;
+        let len = reader.read_u32::<LittleEndian>().ok()?;
+        if len as usize > remaining(&reader) || x >= packet.width || y >= packet.height {
+            return None;
+        }
+
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes).ok()?;
+
+        let frame = decoders.decode(&Packet::from_bytes(&bytes)?)?;
+        tiles.push(Tile { x, y, frame });
+    }
+
+    Some(Update::Tiles {
+        width: packet.width,
+        height: packet.height,
+        refresh: packet.keyframe,
+        tiles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(mut pixel: impl FnMut(usize) -> [u8; 3]) -> Frame {
+        let mut frame = Frame::blank(32, 32, PixelFormat::Bgrx);
+        for (i, p) in frame.data.chunks_exact_mut(4).enumerate() {
+            p[..3].copy_from_slice(&pixel(i));
+        }
+        frame
+    }
+
+    #[test]
+    fn text_on_a_flat_background_is_synthetic() {
+        //  a few dark glyph-ish columns on white
+        let text = tile(|i| {
+            if i % 7 < 2 {
+                [20, 20, 20]
+            } else {
+                [255, 255, 255]
+            }
+        });
+        assert!(looks_synthetic(&text));
+    }
+
+    #[test]
+    fn noise_is_natural() {
+        let mut seed = 12345u32;
+        let noise = tile(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let v = (seed >> 16).to_le_bytes();
+            [v[0], v[1], v[0] ^ v[1]]
+        });
+        assert!(!looks_synthetic(&noise));
+    }
+
+    #[test]
+    fn gradients_are_natural() {
+        let gradient = tile(|i| {
+            [
+                (i % 32 * 8) as u8,
+                (i / 32 * 8) as u8,
+                (i % 32 + i / 32) as u8,
+            ]
+        });
+        assert!(!looks_synthetic(&gradient));
+    }
+
+    fn tiles(width: u32, height: u32, refresh: bool, at: &[(u32, u32)]) -> Update {
+        Update::Tiles {
+            width,
+            height,
+            refresh,
+            tiles: at
+                .iter()
+                .map(|&(x, y)| Tile {
+                    x,
+                    y,
+                    frame: tile(|_| [1, 2, 3]),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn patches_wait_for_a_refresh() {
+        let mut retained = None;
+        assert!(!tiles(64, 64, false, &[(0, 0)]).apply(&mut retained, PixelFormat::Bgrx));
+        assert!(retained.is_none());
+
+        assert!(tiles(64, 64, true, &[(32, 32)]).apply(&mut retained, PixelFormat::Bgrx));
+        let frame = retained.as_ref().unwrap();
+        assert_eq!(&frame.data[..3], &[0, 0, 0]);
+        assert_eq!(&frame.data[(32 * 64 + 32) * 4..][..3], &[1, 2, 3]);
+
+        //  patches go over what's there
+        assert!(tiles(64, 64, false, &[(0, 0)]).apply(&mut retained, PixelFormat::Bgrx));
+        let frame = retained.as_ref().unwrap();
+        assert_eq!(&frame.data[..3], &[1, 2, 3]);
+        assert_eq!(&frame.data[(32 * 64 + 32) * 4..][..3], &[1, 2, 3]);
+        assert_eq!(&frame.data[(32 * 64) * 4..][..3], &[0, 0, 0]);
+
+        //  a different size only takes a refresh
+        assert!(!tiles(96, 64, false, &[(0, 0)]).apply(&mut retained, PixelFormat::Bgrx));
+        assert_eq!(retained.as_ref().unwrap().width, 64);
+    }
+
+    #[test]
+    fn full_frames_replace_what_was_there() {
+        let mut retained = Some(Frame::blank(64, 64, PixelFormat::Bgrx));
+        assert!(Update::Full(tile(|_| [4, 5, 6])).apply(&mut retained, PixelFormat::Rgba));
+        let frame = retained.unwrap();
+        assert_eq!((frame.width, frame.format), (32, PixelFormat::Rgba));
+        assert_eq!(&frame.data[..4], &[6, 5, 4, 0xff]);
+    }
+
+    #[test]
+    fn tiles_outside_the_frame_are_rejected() {
+        let settings = crate::config::HostConfig::default().encoder_settings();
+        let mut encoder = TileEncoder::new(&settings, false);
+        let mut packet = encoder.encode(&tile(|_| [1, 2, 3]));
+        let mut decoders = Decoders::default();
+        assert!(decode(&packet, &mut decoders).is_some());
+
+        //  the first tile's x
+        packet.data[7..11].copy_from_slice(&32u32.to_le_bytes());
+        assert!(decode(&packet, &mut decoders).is_none());
+    }
+
+    /**
+     * remembers the last rate it was given
+     */
+    struct Rate(std::sync::Arc<std::sync::atomic::AtomicU32>);
+
+    impl FrameEncoder for Rate {
+        fn encode(&mut self, frame: &Frame) -> Packet {
+            Packet {
+                codec: CodecId::Qoi,
+                keyframe: true,
+                width: frame.width,
+                height: frame.height,
+                data: vec![],
+            }
+        }
+
+        fn set_bitrate(&mut self, kbps: u32) {
+            self.0.store(kbps, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn tiles_get_their_share_of_the_bitrate() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::sync::Arc;
+
+        let settings = EncoderSettings {
+            tile_size: 64,
+            ..crate::config::HostConfig::default().encoder_settings()
+        };
+        let rate = Arc::new(AtomicU32::new(0));
+        let mut encoder = TileEncoder::new(&settings, false);
+        encoder.inner = Box::new(Rate(rate.clone()));
+
+        //  no grid yet, the whole rate
+        encoder.set_bitrate(6000);
+        assert_eq!(rate.load(Ordering::Relaxed), 6000);
+
+        //  3x2 tiles
+        encoder.encode(&Frame::blank(192, 100, PixelFormat::Bgrx));
+        assert_eq!(rate.load(Ordering::Relaxed), 1000);
+
+        encoder.set_bitrate(3000);
+        assert_eq!(rate.load(Ordering::Relaxed), 500);
+    }
+}
@@ -0,0 +1,364 @@
+use std::os::raw::{c_int, c_uint};
+use std::ptr;
+
+use vpx_sys::*;
+
+use super::yuv::{self, I420};
+use super::{CodecId, EncoderSettings, FrameDecoder, FrameEncoder, Packet};
+use crate::recording::pixels::Frame;
+
+/**
+ * libvpx in realtime mode, no lookahead so every frame comes out
+ * as soon as it went in
+ *
+ * frames are cropped to an even size so the chroma planes line up
+ */
+pub struct VpxEncoder {
+    codec: CodecId,
+    settings: EncoderSettings,
+
+    /**
+     * made on the first frame and again whenever the size changes
+     */
+    ctx: Option<vpx_codec_ctx_t>,
+
+    /**
+     * what `ctx` was made with, kept to change the bitrate in place
+     */
+    cfg: vpx_codec_enc_cfg_t,
+    width: u32,
+    height: u32,
+
+    /**
+     * frames since the last keyframe
+     */
+    since_keyframe: u32,
+    force_keyframe: bool,
+    pts: i64,
+}
+
+//  the context is only ever touched by the thread that owns the encoder
+unsafe impl Send for VpxEncoder {}
+
+impl VpxEncoder {
+    pub fn new(codec: CodecId, settings: &EncoderSettings) -> Self {
+        Self {
+            codec,
+            settings: settings.clone(),
+            ctx: None,
+            cfg: unsafe { std::mem::zeroed() },
+            width: 0,
+            height: 0,
+            since_keyframe: 0,
+            force_keyframe: true,
+            pts: 0,
+        }
+    }
+
+    fn open(&mut self, width: u32, height: u32) {
+        self.close();
+
+        let iface = unsafe {
+            match self.codec {
+                CodecId::Vp9 => vpx_codec_vp9_cx(),
+                _ => vpx_codec_vp8_cx(),
+            }
+        };
+
+        let mut cfg: vpx_codec_enc_cfg_t = unsafe { std::mem::zeroed() };
+        unsafe {
+            assert_eq!(
+                vpx_codec_enc_config_default(iface, &mut cfg, 0),
+                vpx_codec_err_t::VPX_CODEC_OK
+            );
+        }
+
+        cfg.g_w = width;
+        cfg.g_h = height;
+        cfg.g_timebase.num = 1;
+        cfg.g_timebase.den = self.settings.fps as c_int;
+        cfg.g_lag_in_frames = 0;
+        cfg.g_threads = std::thread::available_parallelism()
+            .map(|n| n.get().min(8))
+            .unwrap_or(1) as c_uint;
+        cfg.g_error_resilient = VPX_ERROR_RESILIENT_DEFAULT;
+        cfg.rc_end_usage = vpx_rc_mode::VPX_CBR;
+        cfg.rc_target_bitrate = self.settings.bitrate_kbps;
+        //  we decide when keyframes happen, see `encode`
+        cfg.kf_mode = vpx_kf_mode::VPX_KF_DISABLED;
+
+        let mut ctx: vpx_codec_ctx_t = unsafe { std::mem::zeroed() };
+        unsafe {
+            assert_eq!(
+                vpx_codec_enc_init_ver(&mut ctx, iface, &cfg, 0, VPX_ENCODER_ABI_VERSION as c_int),
+                vpx_codec_err_t::VPX_CODEC_OK,
+                "failed creating {} encoder",
+                self.codec.as_str()
+            );
+
+            //  fastest preset that still looks alright
+            vpx_codec_control_(
+                &mut ctx,
+                vp8e_enc_control_id::VP8E_SET_CPUUSED as c_int,
+                8 as c_int,
+            );
+            if self.codec == CodecId::Vp9 {
+                vpx_codec_control_(
+                    &mut ctx,
+                    vp8e_enc_control_id::VP9E_SET_ROW_MT as c_int,
+                    1 as c_int,
+                );
+                vpx_codec_control_(
+                    &mut ctx,
+                    vp8e_enc_control_id::VP9E_SET_TUNE_CONTENT as c_int,
+                    vp9e_tune_content::VP9E_CONTENT_SCREEN as c_int,
+                );
+            }
+        }
+
+        self.ctx = Some(ctx);
+        self.cfg = cfg;
+        self.width = width;
+        self.height = height;
+        self.force_keyframe = true;
+    }
+
+    fn close(&mut self) {
+        if let Some(mut ctx) = self.ctx.take() {
+            unsafe {
+                vpx_codec_destroy(&mut ctx);
+            }
+        }
+    }
+}
+
+impl FrameEncoder for VpxEncoder {
+    fn encode(&mut self, frame: &Frame) -> Packet {
+        let width = frame.width & !1;
+        let height = frame.height & !1;
+        if self.ctx.is_none() || width != self.width || height != self.height {
+            self.open(width, height);
+        }
+
+        let keyframe = self.force_keyframe || self.since_keyframe >= self.settings.gop;
+        if keyframe {
+            self.since_keyframe = 0;
+            self.force_keyframe = false;
+        }
+        self.since_keyframe += 1;
+
+        //  libvpx wants the planes back to back
+        let yuv = I420::from_frame(frame, width, height);
+        let mut planes = yuv.y;
+        planes.extend_from_slice(&yuv.u);
+        planes.extend_from_slice(&yuv.v);
+
+        let ctx = self.ctx.as_mut().unwrap();
+        let mut data = vec![];
+
+        unsafe {
+            let mut image: vpx_image_t = std::mem::zeroed();
+            vpx_img_wrap(
+                &mut image,
+                vpx_img_fmt::VPX_IMG_FMT_I420,
+                width,
+                height,
+                1,
+                planes.as_mut_ptr(),
+            );
+
+            let flags = if keyframe { VPX_EFLAG_FORCE_KF } else { 0 };
+            let result = vpx_codec_encode(
+                ctx,
+                &image,
+                self.pts,
+                1,
+                flags as vpx_enc_frame_flags_t,
+                VPX_DL_REALTIME as _,
+            );
+            self.pts += 1;
+
+            if result != vpx_codec_err_t::VPX_CODEC_OK {
+                println!(
+                    "failed encoding {} frame: {:?}",
+                    self.codec.as_str(),
+                    result
+                );
+                self.force_keyframe = true;
+            }
+
+            let mut iter = ptr::null();
+            loop {
+                let pkt = vpx_codec_get_cx_data(ctx, &mut iter);
+                if pkt.is_null() {
+                    break;
+                }
+                if (*pkt).kind == vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT {
+                    let f = &(*pkt).data.frame;
+                    data.extend_from_slice(std::slice::from_raw_parts(
+                        f.buf as *const u8,
+                        f.sz as usize,
+                    ));
+                }
+            }
+        }
+
+        Packet {
+            codec: self.codec,
+            keyframe,
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn force_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    /**
+     * libvpx takes a new config mid-stream, no keyframe needed
+     */
+    fn set_bitrate(&mut self, kbps: u32) {
+        self.settings.bitrate_kbps = kbps;
+
+        if let Some(ctx) = self.ctx.as_mut() {
+            self.cfg.rc_target_bitrate = kbps;
+            let result = unsafe { vpx_codec_enc_config_set(ctx, &self.cfg) };
+            if result != vpx_codec_err_t::VPX_CODEC_OK {
+                println!(
+                    "failed setting {} bitrate: {:?}",
+                    self.codec.as_str(),
+                    result
+                );
+            }
+        }
+    }
+}
+
+impl Drop for VpxEncoder {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+pub struct VpxDecoder {
+    codec: CodecId,
+    ctx: vpx_codec_ctx_t,
+
+    /**
+     * false until we've seen a keyframe, anything before it
+     * refers to frames we never got
+     */
+    synced: bool,
+}
+
+impl VpxDecoder {
+    pub fn new(codec: CodecId) -> Self {
+        let iface = unsafe {
+            match codec {
+                CodecId::Vp9 => vpx_codec_vp9_dx(),
+                _ => vpx_codec_vp8_dx(),
+            }
+        };
+
+        let cfg = vpx_codec_dec_cfg_t {
+            threads: std::thread::available_parallelism()
+                .map(|n| n.get().min(4))
+                .unwrap_or(1) as c_uint,
+            w: 0,
+            h: 0,
+        };
+
+        let mut ctx: vpx_codec_ctx_t = unsafe { std::mem::zeroed() };
+        unsafe {
+            assert_eq!(
+                vpx_codec_dec_init_ver(&mut ctx, iface, &cfg, 0, VPX_DECODER_ABI_VERSION as c_int),
+                vpx_codec_err_t::VPX_CODEC_OK,
+                "failed creating {} decoder",
+                codec.as_str()
+            );
+        }
+
+        Self {
+            codec,
+            ctx,
+            synced: false,
+        }
+    }
+}
+
+impl FrameDecoder for VpxDecoder {
+    fn codec(&self) -> CodecId {
+        self.codec
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Option<Frame> {
+        if packet.keyframe {
+            self.synced = true;
+        }
+        if !self.synced || packet.data.is_empty() {
+            return None;
+        }
+
+        unsafe {
+            let result = vpx_codec_decode(
+                &mut self.ctx,
+                packet.data.as_ptr(),
+                packet.data.len() as c_uint,
+                ptr::null_mut(),
+                0,
+            );
+            if result != vpx_codec_err_t::VPX_CODEC_OK {
+                println!(
+                    "failed decoding {} frame: {:?}",
+                    self.codec.as_str(),
+                    result
+                );
+                self.synced = false;
+                return None;
+            }
+
+            //  realtime streams have one picture per packet, keep the last one anyway
+            let mut frame = None;
+            let mut iter = ptr::null();
+            loop {
+                let image = vpx_codec_get_frame(&mut self.ctx, &mut iter);
+                if image.is_null() {
+                    break;
+                }
+
+                let image = &*image;
+                let (width, height) = (image.d_w, image.d_h);
+                let plane = |i: usize, rows: u32| {
+                    let stride = image.stride[i] as usize;
+                    (
+                        std::slice::from_raw_parts(
+                            image.planes[i] as *const u8,
+                            stride * rows as usize,
+                        ),
+                        stride,
+                    )
+                };
+
+                frame = Some(yuv::from_planes(
+                    width,
+                    height,
+                    plane(0, height),
+                    plane(1, (height + 1) / 2),
+                    plane(2, (height + 1) / 2),
+                ));
+            }
+
+            frame
+        }
+    }
+}
+
+impl Drop for VpxDecoder {
+    fn drop(&mut self) {
+        unsafe {
+            vpx_codec_destroy(&mut self.ctx);
+        }
+    }
+}
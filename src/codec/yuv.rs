@@ -0,0 +1,85 @@
+use crate::recording::pixels::{Frame, PixelFormat};
+
+/**
+ * planar 4:2:0 Y, U, V split into its planes, what the video codecs want to be fed
+ *
+ * every plane is tightly packed, chroma planes are half the size
+ * rounded up in each direction
+ */
+pub struct I420 {
+    pub width: u32,
+    pub height: u32,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+}
+
+impl I420 {
+    pub fn chroma_width(&self) -> u32 {
+        (self.width + 1) / 2
+    }
+
+    pub fn chroma_height(&self) -> u32 {
+        (self.height + 1) / 2
+    }
+
+    /**
+     * the top-left `width` x `height` of a frame in any format,
+     * converted with `PixelFormat::I420`'s rules if it isn't already
+     */
+    pub fn from_frame(frame: &Frame, width: u32, height: u32) -> Self {
+        let frame = frame.to(PixelFormat::I420);
+        let width = width.min(frame.width);
+        let height = height.min(frame.height);
+
+        let (full_width, full_height) = (frame.width as usize, frame.height as usize);
+        let full_chroma_width = (full_width + 1) / 2;
+        let full_chroma_size = full_chroma_width * ((full_height + 1) / 2);
+        let (y, chroma) = frame.data.split_at(full_width * full_height);
+        let (u, v) = chroma.split_at(full_chroma_size);
+
+        let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+        Self {
+            width,
+            height,
+            y: crop_plane(y, full_width, width, height),
+            u: crop_plane(u, full_chroma_width, chroma_width, chroma_height),
+            v: crop_plane(v, full_chroma_width, chroma_width, chroma_height),
+        }
+    }
+}
+
+fn crop_plane(plane: &[u8], stride: usize, width: u32, height: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(width as usize * height as usize);
+    for row in plane.chunks(stride).take(height as usize) {
+        out.extend_from_slice(&row[..width as usize]);
+    }
+
+    out
+}
+
+/**
+ * a `PixelFormat::I420` frame from decoder planes that might have
+ * padding at the end of each row, converting is left to whoever shows it
+ */
+pub fn from_planes(
+    width: u32,
+    height: u32,
+    (y, y_stride): (&[u8], usize),
+    (u, u_stride): (&[u8], usize),
+    (v, v_stride): (&[u8], usize),
+) -> Frame {
+    let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+
+    let mut data = Vec::with_capacity(PixelFormat::I420.size(width, height));
+    data.extend_from_slice(&crop_plane(y, y_stride, width, height));
+    data.extend_from_slice(&crop_plane(u, u_stride, chroma_width, chroma_height));
+    data.extend_from_slice(&crop_plane(v, v_stride, chroma_width, chroma_height));
+
+    Frame {
+        data,
+        width,
+        height,
+        format: PixelFormat::I420,
+    }
+}
@@ -0,0 +1,366 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::codec::jpeg::{JpegSettings, Subsampling};
+use crate::codec::{self, CodecId, EncoderSettings};
+use crate::networking::congestion::CongestionSettings;
+use crate::networking::fec::FecSettings;
+use crate::networking::subscribers::{ClientSettings, CursorMode};
+use crate::recording::scale::ScaleFilter;
+
+/**
+ * everything the host can be told on the command line
+ *
+ * `host [target] [--option value]...`
+ */
+#[derive(Clone, Debug)]
+pub struct HostConfig {
+    /**
+     * what to capture, see `recording::windows::WindowMatcher`
+     * and `recording::screen::ScreenTarget`
+     */
+    pub target: String,
+
+    /**
+     * `x11`, `synthetic[:<width>x<height>]` or `playback:<directory or session file>`
+     */
+    pub source: String,
+
+    /**
+     * save everything we stream so it can be played back later,
+     * full size and without the cursor, in the first of `codecs`
+     */
+    pub record_session: Option<PathBuf>,
+
+    /**
+     * only grab a new frame when XDamage says the window changed
+     */
+    pub damage: bool,
+
+    /**
+     * with `damage` on, still send a frame this often
+     * so clients that joined late or lost a packet catch up
+     */
+    pub keep_alive: Duration,
+
+    /**
+     * capture through XComposite so the window can be covered up
+     */
+    pub composite: bool,
+
+    /**
+     * frames a second to capture at when no client asks for a rate
+     */
+    pub fps: u32,
+
+    /**
+     * what frames go out on the wire as, most preferred first,
+     * each client gets the first one it can decode
+     */
+    pub codecs: Vec<CodecId>,
+
+    /**
+     * target bitrate for the inter-frame codecs and adaptive JPEG
+     */
+    pub bitrate_kbps: u32,
+
+    /**
+     * frames from one keyframe to the next for the inter-frame codecs
+     */
+    pub gop: u32,
+    pub jpeg: JpegSettings,
+
+    /**
+     * edge length of the tiles `--codec tiles` diffs frames in,
+     * no bigger than a frame can be
+     */
+    pub tile_size: u32,
+
+    /**
+     * what changed tiles are encoded with
+     */
+    pub tile_codec: CodecId,
+
+    /**
+     * what `--codec hybrid` sends text and UI tiles with
+     */
+    pub lossless_codec: CodecId,
+
+    /**
+     * how many slices `--codec slices` encodes at once, one per core by default
+     */
+    pub slices: u32,
+    pub slice_codec: CodecId,
+
+    /**
+     * parity fragments so clients ride out some loss without a round trip
+     */
+    pub fec: FecSettings,
+
+    /**
+     * how long fragments are kept around for clients that NACK them, 0 never resends
+     */
+    pub retransmit_deadline: Duration,
+
+    /**
+     * lets the bitrate follow what the network takes, `bitrate_kbps` is where it starts
+     */
+    pub congestion: CongestionSettings,
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        Self {
+            target: String::new(),
+            source: String::from("x11"),
+            record_session: None,
+            damage: false,
+            keep_alive: Duration::from_millis(1000),
+            composite: false,
+            fps: 60,
+            codecs: vec![CodecId::Jpeg],
+            bitrate_kbps: 4000,
+            gop: 120,
+            jpeg: JpegSettings::default(),
+            tile_size: 64,
+            tile_codec: CodecId::Jpeg,
+            lossless_codec: CodecId::Qoi,
+            slices: std::thread::available_parallelism()
+                .map(|n| n.get() as u32)
+                .unwrap_or(4),
+            slice_codec: CodecId::Jpeg,
+            fec: FecSettings::default(),
+            retransmit_deadline: Duration::from_millis(150),
+            congestion: CongestionSettings::default(),
+        }
+    }
+}
+
+impl HostConfig {
+    pub fn from_args(args: &[String]) -> Self {
+        let mut config = Self::default();
+
+        let mut args = args.iter().skip(2).peekable();
+        if let Some(target) = args.next_if(|a| !a.starts_with("--")) {
+            config.target = target.clone();
+        }
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--damage" => config.damage = true,
+                "--composite" => config.composite = true,
+                "--fps" => config.fps = parse_value(arg, args.next()),
+                "--codec" => {
+                    config.codecs = CodecId::parse_list(&parse_value::<String>(arg, args.next()));
+                    assert!(
+                        !config.codecs.is_empty(),
+                        "--codec needs a list of jpeg, h264, vp8, vp9, av1, qoi, png, zstd, tiles, slices or hybrid"
+                    );
+                    for codec in &config.codecs {
+                        assert!(
+                            codec.is_available(),
+                            "this build can't do {}, enable its cargo feature",
+                            codec.as_str()
+                        );
+                    }
+                }
+                "--bitrate" => config.bitrate_kbps = parse_value(arg, args.next()),
+                "--gop" => config.gop = parse_value::<u32>(arg, args.next()).max(1),
+                "--tile-size" => {
+                    config.tile_size =
+                        parse_value::<u32>(arg, args.next()).clamp(8, codec::MAX_DIMENSION)
+                }
+                "--tile-codec" => {
+                    config.tile_codec = args
+                        .next()
+                        .and_then(|c| CodecId::parse(c))
+                        .filter(|c| c.is_available() && c.is_intra_only() && *c != CodecId::Slices)
+                        .expect("--tile-codec needs one of jpeg, qoi, png or zstd")
+                }
+                "--hybrid-lossless" => {
+                    config.lossless_codec = args
+                        .next()
+                        .and_then(|c| CodecId::parse(c))
+                        .filter(|c| {
+                            c.is_available()
+                                && [CodecId::Qoi, CodecId::Png, CodecId::Zstd].contains(c)
+                        })
+                        .expect("--hybrid-lossless needs one of qoi, png or zstd")
+                }
+                "--slices" => config.slices = parse_value::<u32>(arg, args.next()).max(1).min(64),
+                "--slice-codec" => {
+                    config.slice_codec = args
+                        .next()
+                        .and_then(|c| CodecId::parse(c))
+                        .filter(|c| c.is_available() && c.is_intra_only() && *c != CodecId::Slices)
+                        .expect("--slice-codec needs one of jpeg, qoi, png or zstd")
+                }
+                "--fec" => config.fec.enabled = true,
+                "--fec-ratio" => {
+                    config.fec.enabled = true;
+                    config.fec.min_ratio = parse_value::<f32>(arg, args.next()).max(0.0).min(1.0)
+                }
+                "--fec-max-ratio" => {
+                    config.fec.max_ratio = parse_value::<f32>(arg, args.next()).max(0.0).min(1.0)
+                }
+                "--retransmit-deadline" => {
+                    config.retransmit_deadline =
+                        Duration::from_millis(parse_value(arg, args.next()))
+                }
+                "--no-congestion-control" => config.congestion.enabled = false,
+                "--min-bitrate" => config.congestion.min_kbps = parse_value(arg, args.next()),
+                "--max-bitrate" => config.congestion.max_kbps = parse_value(arg, args.next()),
+                "--jpeg-quality" => {
+                    config.jpeg.quality = parse_value::<u8>(arg, args.next()).max(1).min(100)
+                }
+                "--jpeg-subsampling" => {
+                    config.jpeg.subsampling = args
+                        .next()
+                        .and_then(|s| Subsampling::parse(s))
+                        .expect("--jpeg-subsampling needs one of 444, 422 or 420")
+                }
+                "--jpeg-baseline" => config.jpeg.progressive = false,
+                "--jpeg-no-trellis" => config.jpeg.trellis = false,
+                "--jpeg-adaptive" => config.jpeg.adaptive = true,
+                "--source" => config.source = parse_value(arg, args.next()),
+                "--record-session" => config.record_session = Some(parse_value(arg, args.next())),
+                "--keep-alive" => {
+                    config.keep_alive = Duration::from_millis(parse_value(arg, args.next()))
+                }
+                _ => println!("ignoring unknown option {}", arg),
+            }
+        }
+
+        config
+    }
+
+    /**
+     * pacing and steering the bitrate only help if every codec we might
+     * stream can follow it, otherwise they just hold frames back
+     */
+    pub fn congestion_control(&self) -> bool {
+        let settings = self.encoder_settings();
+        self.congestion.enabled
+            && self
+                .codecs
+                .iter()
+                .all(|c| crate::codec::follows_bitrate(*c, &settings))
+    }
+
+    pub fn encoder_settings(&self) -> EncoderSettings {
+        EncoderSettings {
+            bitrate_kbps: self.bitrate_kbps,
+            gop: self.gop,
+            fps: self.fps,
+            jpeg: self.jpeg.clone(),
+            tile_size: self.tile_size,
+            tile_codec: self.tile_codec,
+            lossless_codec: self.lossless_codec,
+            slices: self.slices,
+            slice_codec: self.slice_codec,
+            threads: 1,
+        }
+    }
+}
+
+/**
+ * `[--option value]...`
+ */
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    pub cursor: CursorMode,
+    pub fps: Option<u32>,
+
+    /**
+     * what we're willing to decode, everything this build can by default
+     */
+    pub codecs: Vec<CodecId>,
+
+    /**
+     * `--size 1280x720`, frames bigger than this get scaled down by the host
+     */
+    pub size: Option<(u32, u32)>,
+    pub filter: ScaleFilter,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            cursor: CursorMode::Blend,
+            fps: None,
+            codecs: CodecId::available(),
+            size: None,
+            filter: ScaleFilter::Bilinear,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn from_args(args: &[String]) -> Self {
+        let mut config = Self::default();
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--cursor" => {
+                    config.cursor = args
+                        .next()
+                        .and_then(|m| CursorMode::parse(m))
+                        .expect("--cursor needs one of hidden, blend or metadata")
+                }
+                "--fps" => config.fps = Some(parse_value(arg, args.next())),
+                "--codecs" => {
+                    config.codecs = CodecId::parse_list(&parse_value::<String>(arg, args.next()))
+                        .into_iter()
+                        .filter(|c| c.is_available())
+                        .collect()
+                }
+                "--size" => {
+                    config.size = Some(
+                        args.next()
+                            .and_then(|s| parse_size(s))
+                            .expect("--size needs <width>x<height>"),
+                    )
+                }
+                "--scale-filter" => {
+                    config.filter = args
+                        .next()
+                        .and_then(|f| ScaleFilter::parse(f))
+                        .expect("--scale-filter needs one of nearest, bilinear or lanczos")
+                }
+                _ => println!("ignoring unknown option {}", arg),
+            }
+        }
+
+        config
+    }
+
+    /**
+     * the part of the config the host needs to know about
+     */
+    pub fn settings(&self) -> ClientSettings {
+        ClientSettings {
+            cursor: self.cursor,
+            fps: self.fps,
+            codecs: self.codecs.clone(),
+            size: self.size,
+            filter: self.filter,
+        }
+    }
+}
+
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    Some((width, height))
+}
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: Option<&String>) -> T {
+    value
+        .and_then(|v| v.parse().ok())
+        .expect(&format!("{} needs a valid value", option))
+}
@@ -1,12 +1,16 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::env;
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
-use std::u64;
 use zmq::SNDMORE;
 
+mod codec;
+mod config;
 mod networking;
 mod recording;
+mod streaming;
 
 #[macro_use]
 extern crate glium;
@@ -21,7 +25,11 @@ struct SnowVertex {
 fn host(args: Vec<String>) {}
 
 #[cfg(target_os = "linux")]
-fn handshake(context: &zmq::Context) {
+fn handshake(
+    context: &zmq::Context,
+    subscribers: networking::subscribers::Subscribers,
+    preferred: Vec<codec::CodecId>,
+) {
     let rw_primary = context.socket(zmq::ROUTER).unwrap();
     assert!(rw_primary
         .bind(&format!("tcp://*:{}", networking::HOST_PRIMARY_PORT))
@@ -29,12 +37,19 @@ fn handshake(context: &zmq::Context) {
 
     loop {
         {
-            let identity = rw_primary.recv_string(0).unwrap().unwrap();
+            let mut identity_msg = rw_primary.recv_msg(0).unwrap();
+            let peer = identity_msg
+                .gets("Peer-Address")
+                .and_then(|a| a.parse::<std::net::IpAddr>().ok());
+            let identity = String::from(identity_msg.as_str().unwrap());
 
             //  read empty string
             rw_primary.recv_string(0).unwrap().unwrap();
 
-            let envelope = rw_primary.recv_string(0).unwrap().unwrap();
+            let envelope = match rw_primary.recv_string(0) {
+                Ok(Ok(envelope)) => envelope,
+                _ => String::from(""),
+            };
             println!("[H] [{}] envelope: {}", identity, envelope); // Envelope
 
             if envelope == "SYN" {
@@ -47,6 +62,50 @@ fn handshake(context: &zmq::Context) {
                 rw_primary.send("", SNDMORE).unwrap();
                 rw_primary.send("NAME_OK", 0).unwrap();
                 continue;
+            } else if envelope == "SUBSCRIBE" {
+                //  take all of it, so nothing is left over for the next message
+                let parts = if rw_primary.get_rcvmore().unwrap_or(false) {
+                    rw_primary.recv_multipart(0).unwrap_or_default()
+                } else {
+                    vec![]
+                };
+                let (port, settings) = match parse_subscription(&parts) {
+                    Some(subscription) => subscription,
+                    None => {
+                        println!("[H] [{}] garbled subscription", identity);
+                        rw_primary.send(&identity, SNDMORE).unwrap();
+                        rw_primary.send("", SNDMORE).unwrap();
+                        rw_primary.send("SUBSCRIBE_ERR", 0).unwrap();
+                        continue;
+                    }
+                };
+                let codec = codec::CodecId::negotiate(&preferred, &settings.codecs);
+                let address = std::net::SocketAddr::new(
+                    peer.unwrap_or_else(|| std::net::Ipv4Addr::LOCALHOST.into()),
+                    port,
+                );
+                println!(
+                    "[H] [{}] subscribed from {} {:?}, streaming {}",
+                    identity,
+                    address,
+                    settings,
+                    codec.as_str()
+                );
+
+                subscribers.lock().unwrap().insert(
+                    identity.clone(),
+                    networking::subscribers::Subscriber {
+                        user_id: identity.clone(),
+                        address,
+                        settings,
+                        codec,
+                    },
+                );
+
+                rw_primary.send(&identity, SNDMORE).unwrap();
+                rw_primary.send("", SNDMORE).unwrap();
+                rw_primary.send("SUBSCRIBE_OK", 0).unwrap();
+                continue;
             } else if envelope == "input" {
                 println!(
                     "[H] [{}] pressed {:?}",
@@ -76,10 +135,275 @@ fn handshake(context: &zmq::Context) {
     }
 }
 
+/**
+ * the port and settings that come after a SUBSCRIBE envelope,
+ * `None` if anything is missing or garbled
+ */
 #[cfg(target_os = "linux")]
-fn send_frames(context: &zmq::Context) {
-    let args: Vec<String> = env::args().collect();
-    let display = recording::open_display();
+fn parse_subscription(parts: &[Vec<u8>]) -> Option<(u16, networking::subscribers::ClientSettings)> {
+    let number = |i: usize| bincode::deserialize::<u32>(parts.get(i)?).ok();
+    let text = |i: usize| std::str::from_utf8(parts.get(i)?).ok();
+
+    let port = u16::try_from(number(0)?).ok()?;
+    let fps = number(2)?;
+    let (width, height) = (number(4)?, number(5)?);
+
+    let settings = networking::subscribers::ClientSettings {
+        cursor: networking::subscribers::CursorMode::parse(text(1)?)
+            .unwrap_or(networking::subscribers::CursorMode::Blend),
+        fps: if fps == 0 { None } else { Some(fps) },
+        codecs: codec::CodecId::parse_list(text(3)?),
+        size: if width == 0 || height == 0 {
+            None
+        } else {
+            Some((width, height))
+        },
+        filter: recording::scale::ScaleFilter::parse(text(6)?)
+            .unwrap_or(recording::scale::ScaleFilter::Bilinear),
+    };
+
+    Some((port, settings))
+}
+
+#[cfg(target_os = "linux")]
+fn send_frames(
+    context: &zmq::Context,
+    config: config::HostConfig,
+    subscribers: networking::subscribers::Subscribers,
+) {
+    use streaming::{pipeline, queue};
+
+    //  a couple of frames of slack, anything more is just latency
+    let (captured_tx, captured_rx) = queue::bounded(2);
+    let (encoded_tx, encoded_rx) = queue::bounded(2);
+    let encoded_stats = encoded_tx.stats();
+
+    {
+        let ctx = context.clone();
+        let config = config.clone();
+        thread::spawn(move || capture_frames(&ctx, config, subscribers, captured_tx));
+    }
+
+    let encoders = pipeline::encoder_count(&config.codecs);
+    let settings = codec::EncoderSettings {
+        threads: encoders as u32,
+        ..config.encoder_settings()
+    };
+    println!(
+        "[H] encoding {} on {} threads",
+        codec::CodecId::list_to_string(&config.codecs),
+        encoders
+    );
+    if config.codecs.iter().any(|c| !c.is_intra_only()) {
+        println!(
+            "[H] {} kbps at {} fps, keyframe every {} frames",
+            settings.bitrate_kbps, settings.fps, settings.gop
+        );
+    }
+    if config.codecs.contains(&codec::CodecId::Jpeg) {
+        println!("[H] {:?}", settings.jpeg);
+    }
+
+    //  the sender decides, the encoders follow
+    let target = networking::congestion::TargetBitrate::new(settings.bitrate_kbps);
+    let recorded = config
+        .record_session
+        .as_ref()
+        .map(|_| pipeline::Variant::recorded(config.codecs[0]));
+    for _ in 0..encoders {
+        let captured_rx = captured_rx.clone();
+        let encoded_tx = encoded_tx.clone();
+        let settings = settings.clone();
+        let target = target.clone();
+        thread::spawn(move || {
+            pipeline::encode_frames(settings, recorded, target, captured_rx, encoded_tx)
+        });
+    }
+    //  only the encoders hold on to it, so the sender stops once they're all gone
+    drop(encoded_tx);
+
+    send_encoded(config, target, encoded_rx, encoded_stats);
+}
+
+/**
+ * first stage of the pipeline, grabs frames at the paced rate
+ * and decides who they're for
+ */
+#[cfg(target_os = "linux")]
+fn capture_frames(
+    context: &zmq::Context,
+    config: config::HostConfig,
+    subscribers: networking::subscribers::Subscribers,
+    captured: streaming::queue::Sender<streaming::pipeline::Captured>,
+) {
+    use networking::subscribers::CursorMode;
+
+    //  X11 connections can't be moved between threads, open it here
+    let mut source = recording::source::open(&config);
+
+    let w_cursor = context.socket(zmq::PUB).unwrap();
+    w_cursor
+        .bind(&format!("tcp://*:{}", networking::HOST_CURSOR_STREAM_PORT))
+        .expect("Failed binding cursor socket for host");
+
+    //  nothing went out yet, the first frame goes whether or not anything changed
+    let mut last_sent: Option<Instant> = None;
+    let mut last_cursor: Option<recording::cursor::Cursor> = None;
+    //  clients that scaled the frame down need this to place the cursor on it
+    let mut captured_size: Option<(u32, u32)> = None;
+    let publish_cursor = |c: &recording::cursor::Cursor, size: (u32, u32)| {
+        w_cursor
+            .send("cursor", zmq::SNDMORE)
+            .expect("failed sending cursor envelope");
+        w_cursor
+            .send(bincode::serialize(c).unwrap(), zmq::SNDMORE)
+            .expect("failed sending cursor");
+        w_cursor
+            .send(bincode::serialize(&size).unwrap(), 0)
+            .expect("failed sending cursor frame size");
+    };
+
+    let mut pacer = streaming::pacing::Pacer::new(config.fps);
+    //  when each subscriber that asked for a lower rate than the pacer's is due next
+    let mut due: HashMap<String, Instant> = HashMap::new();
+    let captured_stats = captured.stats();
+
+    //  who got the last frame, anyone else needs a keyframe to start from
+    let mut watching: Vec<String> = vec![];
+    let mut keyframe = false;
+
+    let mut seq = 0;
+
+    loop {
+        if let Some(report) = pacer.report() {
+            println!(
+                "[H] {:.1} of {} fps, skipped {} frames, {} dropped before encoding",
+                report.achieved_fps,
+                report.target_fps,
+                report.skipped,
+                captured_stats.take_dropped()
+            );
+        }
+        pacer.wait();
+
+        let subscribers: Vec<networking::subscribers::Subscriber> =
+            subscribers.lock().unwrap().values().cloned().collect();
+
+        //  run as fast as the most demanding client, the others get every nth frame
+        pacer.set_fps(
+            subscribers
+                .iter()
+                .filter_map(|s| s.settings.fps)
+                .max()
+                .unwrap_or(config.fps),
+        );
+        due.retain(|id, _| subscribers.iter().any(|s| &s.user_id == id));
+
+        if subscribers.iter().any(|s| !watching.contains(&s.user_id)) {
+            keyframe = true;
+        }
+        watching = subscribers.iter().map(|s| s.user_id.clone()).collect();
+
+        let now = Instant::now();
+        //  the slower subscribers this frame is for, their next one is only
+        //  due an interval later if it actually goes out
+        let mut paced: Vec<(String, Duration)> = vec![];
+        let subscribers: Vec<networking::subscribers::Subscriber> = subscribers
+            .into_iter()
+            .filter(|s| {
+                //  skipping frames breaks inter-frame codecs, everybody gets all of them
+                let fps = s.settings.fps.unwrap_or_else(|| pacer.fps());
+                if fps >= pacer.fps() || !s.codec.is_intra_only() {
+                    return true;
+                }
+
+                //  half a pacer slot of slack so rounding doesn't cost a frame
+                let slack = streaming::pacing::Pacer::interval_for(pacer.fps()) / 2;
+                if due.get(&s.user_id).is_some_and(|next| now + slack < *next) {
+                    return false;
+                }
+
+                paced.push((
+                    s.user_id.clone(),
+                    streaming::pacing::Pacer::interval_for(fps),
+                ));
+                true
+            })
+            .collect();
+
+        let changed = source.changed();
+        let wants = |mode: CursorMode| subscribers.iter().any(|s| s.settings.cursor == mode);
+
+        let cursor = if wants(CursorMode::Blend) || wants(CursorMode::Metadata) {
+            source.cursor()
+        } else {
+            None
+        };
+        let cursor_moved = cursor != last_cursor;
+        if cursor_moved && wants(CursorMode::Metadata) {
+            if let (Some(c), Some(size)) = (cursor.as_ref(), captured_size) {
+                publish_cursor(c, size);
+            }
+        }
+        last_cursor = cursor.clone();
+
+        //  the cursor isn't part of the window, moving it doesn't damage anything
+        let changed = changed || (cursor_moved && wants(CursorMode::Blend)) || keyframe;
+        if !changed && last_sent.is_some_and(|t| t.elapsed() < config.keep_alive) {
+            continue;
+        }
+
+        let frame = match source.capture() {
+            Some(frame) => frame,
+            None => continue,
+        };
+        if captured_size != Some((frame.width, frame.height)) {
+            captured_size = Some((frame.width, frame.height));
+            if let (Some(c), true) = (cursor.as_ref(), wants(CursorMode::Metadata)) {
+                publish_cursor(c, (frame.width, frame.height));
+            }
+        }
+
+        for (id, interval) in paced {
+            let next = due.entry(id).or_insert(now);
+            *next = (*next + interval).max(now);
+        }
+
+        last_sent = Some(Instant::now());
+        seq += 1;
+        //  the frame that asked for a keyframe might be the one thrown out,
+        //  then whoever is waiting for it needs the next one to ask again
+        let evicted = captured.push_evicting(streaming::pipeline::Captured {
+            seq,
+            frame,
+            cursor,
+            subscribers,
+            keyframe,
+        });
+        keyframe = !keyframe && evicted.iter().any(|c| c.keyframe);
+        pacer.frame_sent();
+    }
+}
+
+/**
+ * how often the host looks for feedback when no frame is going out
+ */
+const FEEDBACK_POLL: Duration = Duration::from_millis(5);
+
+/**
+ * last stage of the pipeline, puts encoded frames on the wire in order
+ */
+#[cfg(target_os = "linux")]
+fn send_encoded(
+    config: config::HostConfig,
+    target: networking::congestion::TargetBitrate,
+    encoded: streaming::queue::Receiver<streaming::pipeline::Encoded>,
+    encoded_stats: streaming::queue::QueueStats,
+) {
+    let mut session = config.record_session.as_ref().map(|path| {
+        recording::source::SessionWriter::create(path)
+            .expect(&format!("failed creating {}", path.display()))
+    });
 
     //    let w_frame = context.socket(zmq::PUB).unwrap();
     //    w_frame
@@ -92,38 +416,230 @@ fn send_frames(context: &zmq::Context) {
     // Create a poll instance.
     let mut poll = Poll::new().unwrap();
 
-    // Setup the UDP socket.
-    let addr = format!("127.0.0.1:{}", networking::HOST_FRAME_STREAM_PORT)
+    //  subscribers aren't necessarily on this machine
+    let addr = format!("0.0.0.0:{}", networking::HOST_FRAME_STREAM_PORT)
         .parse()
         .unwrap();
-    let mut subscribers: Vec<std::net::SocketAddr> = vec![];
-
-    subscribers.push(
-        format!("127.0.0.1:{}", networking::CLIENT_FRAME_STREAM_PORT)
-            .parse()
-            .unwrap(),
-    );
 
     let mut socket = UdpSocket::bind(addr).unwrap();
 
-    // Register our socket with the token defined above and an interest in being
-    // `READABLE`.
+    //  writable for frames, readable for what clients report back
     poll.registry()
-        .register(&mut socket, UDP_SOCKET, Interest::WRITABLE)
+        .register(
+            &mut socket,
+            UDP_SOCKET,
+            Interest::WRITABLE | Interest::READABLE,
+        )
         .unwrap();
 
+    let mut events = Events::with_capacity(1);
+    let mut feedback_buf = [0; 1500];
+    let mut redundancy: HashMap<std::net::SocketAddr, networking::fec::Redundancy> = HashMap::new();
+    let mut retransmit = networking::retransmit::RetransmitBuffer::new(config.retransmit_deadline);
+    let mut estimators: HashMap<std::net::SocketAddr, networking::congestion::BandwidthEstimator> =
+        HashMap::new();
+    let congestion_control = config.congestion_control();
+    if config.congestion.enabled && !congestion_control {
+        println!(
+            "[H] no congestion control, not every codec follows a bitrate (try --jpeg-adaptive)"
+        );
+    }
+    let mut pacer = if congestion_control {
+        Some(streaming::pacing::PacketPacer::new(target.get()))
+    } else {
+        None
+    };
+
+    //  frame ids count what each client was actually sent, with no holes, so the
+    //  ones it never sees are lost and not just skipped by capture or pacing
+    let mut frame_ids: HashMap<std::net::SocketAddr, u32> = HashMap::new();
+
+    let mut last_seq = 0;
+    let mut out_of_order = 0;
+    let mut last_report = Instant::now();
+
     let mut fc = 0;
 
+    //  feedback has to be read, and lost fragments resent,
+    //  even while the encoders have nothing new for us
+    let ticker = crossbeam_channel::tick(FEEDBACK_POLL);
+
     loop {
-        let xid = u64::from_str_radix(&args[2], 16).unwrap();
-        let image = recording::record_linux(display, xid);
-        {
-            let packet = image.data.as_ref().unwrap().to_vec();
+        let e = crossbeam_channel::select! {
+            recv(encoded) -> e => match e {
+                Ok(e) => Some(e),
+                Err(_) => return,
+            },
+            recv(ticker) -> _ => None,
+        };
+
+        while let Ok((size, from)) = socket.recv_from(&mut feedback_buf) {
+            match networking::feedback::Feedback::from_bytes(&feedback_buf[..size]) {
+                Some(networking::feedback::Feedback::Report {
+                    expected,
+                    arrived,
+                    bytes,
+                    arrivals,
+                }) => {
+                    redundancy
+                        .entry(from)
+                        .or_insert_with(|| networking::fec::Redundancy::new(&config.fec))
+                        .report(expected, arrived);
+                    if congestion_control {
+                        estimators
+                            .entry(from)
+                            .or_insert_with(|| {
+                                networking::congestion::BandwidthEstimator::new(
+                                    &config.congestion,
+                                    config.bitrate_kbps,
+                                )
+                            })
+                            .report(expected, arrived, bytes, &arrivals, Instant::now());
+                    }
+                }
+                Some(networking::feedback::Feedback::Nack { frame_id, indexes }) => {
+                    let resend = retransmit.get(from, frame_id, &indexes, Instant::now());
+                    for fragment in &resend {
+                        send_datagram(
+                            &socket,
+                            &mut poll,
+                            &mut events,
+                            pacer.as_mut(),
+                            &fragment.to_bytes(),
+                            from,
+                        );
+                    }
+                    if indexes.is_empty() {
+                        println!(
+                            "[H] {} lost all of frame {}, resent {} fragments",
+                            from,
+                            frame_id,
+                            resend.len()
+                        );
+                    } else {
+                        println!(
+                            "[H] {} lost {} fragments of frame {}, resent {}",
+                            from,
+                            indexes.len(),
+                            frame_id,
+                            resend.len()
+                        );
+                    }
+                }
+                None => println!("[H] garbage feedback from {}", from),
+            }
+        }
+        retransmit.expire(Instant::now());
+
+        //  one encoder per variant is shared by everyone watching it, so the
+        //  slowest client sets the rate, the pacer has to fit all of them
+        if let Some(pacer) = pacer.as_mut() {
+            let now = Instant::now();
+            estimators.retain(|_, e| e.idle(now) < Duration::from_secs(5));
 
-            for s in &subscribers {
-                socket.send_to(&packet, *s).unwrap();
+            let targets: Vec<u32> = estimators.values().map(|e| e.target_kbps()).collect();
+            if let Some(&slowest) = targets.iter().min() {
+                target.set(slowest);
+            }
+            pacer.set_bitrate(targets.iter().sum::<u32>().max(target.get()));
+        }
+
+        let e = match e {
+            Some(e) => e,
+            None => continue,
+        };
+
+        //  the encoders race each other, a frame that finished
+        //  after a newer one is no use to anybody
+        if e.seq <= last_seq {
+            out_of_order += 1;
+            continue;
+        }
+        last_seq = e.seq;
+
+        if last_report.elapsed() >= Duration::from_secs(5) {
+            println!(
+                "[H] {} frames finished out of order, {} dropped before sending",
+                out_of_order,
+                encoded_stats.take_dropped()
+            );
+            for (address, estimator) in &estimators {
+                println!(
+                    "[H] {} gets {} kbps, {:?}",
+                    address,
+                    estimator.target_kbps(),
+                    estimator.usage()
+                );
+            }
+            for (address, redundancy) in redundancy.iter().filter(|_| config.fec.enabled) {
+                println!(
+                    "[H] {} loses {:.1}% of datagrams, one parity per {} fragments",
+                    address,
+                    redundancy.loss() * 100.0,
+                    redundancy.group()
+                );
+            }
+            out_of_order = 0;
+            last_report = Instant::now();
+        }
+
+        if let Some(session) = session.as_mut() {
+            let recorded = streaming::pipeline::Variant::recorded(config.codecs[0]);
+            if let Some(packet) = e.packets.get(&recorded) {
+                session.write(packet).expect("failed writing session");
+            }
+        }
+
+        for s in &e.subscribers {
+            let variant = streaming::pipeline::Variant::of(s);
+
+            if let Some(packet) = e.packets.get(&variant) {
+                let frame_id = frame_ids.entry(s.address).or_insert(0);
+                *frame_id = frame_id.wrapping_add(1);
+                let frame_id = *frame_id;
+
+                let fragments = networking::fragments::split(frame_id, packet);
+                let fragments = if config.fec.enabled {
+                    let redundancy = redundancy
+                        .entry(s.address)
+                        .or_insert_with(|| networking::fec::Redundancy::new(&config.fec));
+                    networking::fec::protect(&fragments, redundancy.group())
+                } else {
+                    fragments
+                };
+                for fragment in &fragments {
+                    send_datagram(
+                        &socket,
+                        &mut poll,
+                        &mut events,
+                        pacer.as_mut(),
+                        &fragment.to_bytes(),
+                        s.address,
+                    );
+                }
+                if congestion_control {
+                    estimators
+                        .entry(s.address)
+                        .or_insert_with(|| {
+                            networking::congestion::BandwidthEstimator::new(
+                                &config.congestion,
+                                config.bitrate_kbps,
+                            )
+                        })
+                        .sent(frame_id, Instant::now());
+                }
                 fc += 1;
-                println!("sent frame {} to: {}", fc, *s);
+                println!(
+                    "sent {} frame {} ({}x{}, {} fragments) to: {} ({})",
+                    packet.codec.as_str(),
+                    fc,
+                    packet.width,
+                    packet.height,
+                    fragments.len(),
+                    s.user_id,
+                    s.address
+                );
+                retransmit.push(s.address, frame_id, fragments, Instant::now());
             }
 
             /*
@@ -141,14 +657,13 @@ fn send_frames(context: &zmq::Context) {
                 .expect("failed sending frame");
                 */
         }
-
-        thread::sleep(Duration::from_millis(1));
     }
 }
 
 use mio::net::UdpSocket;
 use mio::{Events, Interest, Poll, Token};
 use std::io;
+use std::net::ToSocketAddrs;
 
 fn host_udp() -> io::Result<()> {
     // Our event loop.
@@ -355,14 +870,18 @@ fn host(args: Vec<String>) {
     */
 
     let context = zmq::Context::new();
+    let config = config::HostConfig::from_args(&args);
+    let subscribers = networking::subscribers::Subscribers::default();
 
     {
         let ctx = context.clone();
-        thread::spawn(move || handshake(&ctx));
+        let subscribers = subscribers.clone();
+        let preferred = config.codecs.clone();
+        thread::spawn(move || handshake(&ctx, subscribers, preferred));
     }
     {
         let ctx = context.clone();
-        thread::spawn(move || send_frames(&ctx));
+        thread::spawn(move || send_frames(&ctx, config, subscribers));
     }
     {
         let ctx = context.clone();
@@ -372,6 +891,31 @@ fn host(args: Vec<String>) {
     loop {}
 }
 
+#[cfg(target_os = "linux")]
+fn list_windows() {
+    let display = recording::open_display();
+
+    for w in recording::windows::list_windows(display) {
+        println!(
+            "{:#010x} {:>5}x{:<5} pid {:<7} class {:<30} {}{}",
+            w.xid,
+            w.width,
+            w.height,
+            w.pid.map_or(String::from("-"), |p| p.to_string()),
+            w.class.join("."),
+            w.title.as_deref().unwrap_or(""),
+            if w.viewable { "" } else { " (unmapped)" }
+        );
+    }
+
+    for (name, area) in recording::screen::list_outputs(display) {
+        println!(
+            "output:{} {}x{} at {},{}",
+            name, area.width, area.height, area.x, area.y
+        );
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn hex(bytes: &[u8]) -> String {
     bytes
@@ -413,7 +957,9 @@ fn worker_task(id: String) {
         }
     }
 
-    do_client_stuff(client);
+    client.subscribe(&networking::subscribers::ClientSettings::default());
+
+    do_client_stuff(client, config::ClientConfig::default());
     /*
     loop {
 
@@ -512,7 +1058,12 @@ fn main() {
 
     if args.len() != 1 && args[1] == "host" {
         host(args);
+    } else if args.len() != 1 && args[1] == "list-windows" {
+        list_windows();
+    } else if args.len() != 1 && args[1] == "bench" {
+        codec::bench::run(&args);
     } else {
+        let config = config::ClientConfig::from_args(&args);
         let mut client = networking::Client::new();
         client.connect(String::from("192.168.0.103"), String::from("tony"));
 
@@ -543,11 +1094,13 @@ fn main() {
             }
         }
 
-        do_client_stuff(client);
+        client.subscribe(&config.settings());
+
+        do_client_stuff(client, config);
     }
 }
 
-fn do_client_stuff(client: networking::Client) {
+fn do_client_stuff(client: networking::Client, config: config::ClientConfig) {
     // 1. The **winit::EventsLoop** for handling events.
     let event_loop = glium::glutin::event_loop::EventLoop::new();
     // 2. Parameters for building the Window.
@@ -586,8 +1139,7 @@ out vec2 v_tex_coords;
   
    void main()
    {
-      vec4 pre = texture(tex, v_tex_coords);
-      color.rgba = pre.bgra;
+      color = texture(tex, v_tex_coords);
   }"#;
     let program = glium::Program::from_source(&display, vertex_src, fragment_src, None).unwrap();
 
@@ -620,17 +1172,74 @@ out vec2 v_tex_coords;
         height: 0,
     });
 
+    //  latest cursor published by the host and the size of the frame it was placed on,
+    //  only used when we draw it ourselves
+    let cursor = std::sync::Arc::new(std::sync::Mutex::new(
+        None::<(recording::cursor::Cursor, (u32, u32))>,
+    ));
+
+    if config.cursor == networking::subscribers::CursorMode::Metadata {
+        let r_cursor = context.socket(zmq::SUB).unwrap();
+        r_cursor
+            .connect(&format!(
+                "tcp://{}:{}",
+                "localhost",
+                networking::HOST_CURSOR_STREAM_PORT
+            ))
+            .expect(&format!(
+                "[C] [{}] failed connecting to cursor stream",
+                client.user_id
+            ));
+        r_cursor
+            .set_subscribe(b"cursor")
+            .expect("failed subscribing");
+
+        let cursor = cursor.clone();
+        thread::spawn(move || loop {
+            let _envelope = r_cursor
+                .recv_string(0)
+                .expect("failed receiving envelope")
+                .unwrap();
+            let message = r_cursor.recv_bytes(0).expect("failed receiving cursor");
+            let size = r_cursor
+                .recv_bytes(0)
+                .expect("failed receiving cursor frame size");
+
+            *cursor.lock().unwrap() = bincode::deserialize(&message)
+                .ok()
+                .zip(bincode::deserialize(&size).ok());
+        });
+    }
+
     static mut RENDERER: Renderer = Renderer::new();
 
+    let host = client.url.clone();
     thread::spawn(move || {
-        let mut facade = NetFacade::new();
+        let mut facade = NetFacade::new(&host);
 
         loop {
             unsafe {
-                let frame = facade.get_frame();
+                let renderer = &mut *std::ptr::addr_of_mut!(RENDERER);
+
+                //  nothing to show until a packet patches the retained frame
+                let mut frame = match facade.get_update().and_then(|u| renderer.patch(u)) {
+                    Some(frame) => frame,
+                    None => continue,
+                };
+
+                //  the cursor is placed in captured pixels, with `--size`
+                //  the host scaled the frame down so it has to follow
+                if let Some((c, size)) = cursor.lock().unwrap().as_ref() {
+                    let c = c.scaled(*size, (frame.width, frame.height));
+                    recording::cursor::blend(&mut frame, &c);
+                }
 
-                RENDERER.write(frame);
-                RENDERER.swap_buffers();
+                renderer.write(SnowFrame {
+                    width: frame.width,
+                    height: frame.height,
+                    data: Some(frame.data),
+                });
+                renderer.swap_buffers();
             }
 
             /*
@@ -777,6 +1386,11 @@ struct Renderer {
     buffers: [SnowFrame; 2],
     read_from: usize,
     write_to: usize,
+
+    /**
+     * the picture as of the last packet, tile updates are drawn over it
+     */
+    retained: Option<recording::pixels::Frame>,
 }
 
 impl Renderer {
@@ -796,9 +1410,24 @@ impl Renderer {
             ],
             read_from: 0,
             write_to: 1,
+            retained: None,
         }
     }
 
+    /**
+     * applies the update to the retained frame and hands back a copy to draw,
+     * `None` while we're waiting for a full refresh
+     *
+     * frames are kept as RGBA, the way they get uploaded to the texture
+     */
+    fn patch(&mut self, update: codec::tiles::Update) -> Option<recording::pixels::Frame> {
+        if !update.apply(&mut self.retained, recording::pixels::PixelFormat::Rgba) {
+            return None;
+        }
+
+        self.retained.clone()
+    }
+
     fn read(&self) -> &SnowFrame {
         &self.buffers[self.read_from]
     }
@@ -814,27 +1443,106 @@ impl Renderer {
     }
 }
 
+/**
+ * a frame is a burst of fragments now, more than the socket buffer
+ * takes at once, so wait for room instead of giving up
+ *
+ * with congestion control on the pacer spreads them out first
+ */
+#[cfg(target_os = "linux")]
+fn send_datagram(
+    socket: &UdpSocket,
+    poll: &mut Poll,
+    events: &mut Events,
+    pacer: Option<&mut streaming::pacing::PacketPacer>,
+    bytes: &[u8],
+    address: std::net::SocketAddr,
+) {
+    if let Some(pacer) = pacer {
+        pacer.wait(bytes.len());
+    }
+
+    loop {
+        match socket.send_to(bytes, address) {
+            Ok(_) => return,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                poll.poll(events, Some(Duration::from_millis(5))).unwrap();
+            }
+            Err(e) => {
+                println!("failed sending to {}: {}", address, e);
+                return;
+            }
+        }
+    }
+}
+
 // A token to allow us to identify which event is for the `UdpSocket`.
 const UDP_SOCKET: Token = Token(0);
 
+/**
+ * how long the client waits for the rest of a frame's fragments
+ */
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/**
+ * the render thread's end of the frame stream, packets come in
+ * from a `FrameReceiver` on its own thread and get decoded here
+ */
 struct NetFacade {
+    packets: crossbeam_channel::Receiver<codec::Packet>,
+    decoders: codec::Decoders,
+}
+
+impl NetFacade {
+    fn new(host: &str) -> Self {
+        //  not bounded, inter-frame decoders need every packet
+        let (tx, packets) = crossbeam_channel::unbounded();
+        let receiver = FrameReceiver::new(host);
+        thread::spawn(move || receiver.run(tx));
+
+        Self {
+            packets,
+            decoders: codec::Decoders::default(),
+        }
+    }
+
+    fn get_update(&mut self) -> Option<codec::tiles::Update> {
+        //  the decoder matching the packet's codec,
+        //  broken or unknown packets are skipped
+        let packet = self.packets.recv().ok()?;
+        self.decoders.decode_update(&packet)
+    }
+}
+
+/**
+ * drains the frame socket and puts fragments back together
+ *
+ * it runs apart from rendering so datagrams are timestamped when they
+ * come in, vsync and decoding would look like network delay to the host
+ */
+struct FrameReceiver {
     events: Events,
     poll: Poll,
     socket: UdpSocket,
     buf: [u8; 1 << 16],
+    reassembler: networking::fragments::Reassembler,
+    last_report: Instant,
 }
 
-impl NetFacade {
-    fn new() -> Self {
+impl FrameReceiver {
+    /**
+     * `host` is what the client connected to, feedback goes back there
+     */
+    fn new(host: &str) -> Self {
         // Create storage for events. Since we will only register a single socket, a
         // capacity of 1 will do.
-        let mut events = Events::with_capacity(1);
+        let events = Events::with_capacity(1);
 
         // Create a poll instance.
-        let mut poll = Poll::new().unwrap();
+        let poll = Poll::new().unwrap();
 
-        // Setup the UDP socket.
-        let addr = format!("127.0.0.1:{}", networking::CLIENT_FRAME_STREAM_PORT)
+        //  the host sends from wherever it is, not just this machine
+        let addr = format!("0.0.0.0:{}", networking::CLIENT_FRAME_STREAM_PORT)
             .parse()
             .unwrap();
 
@@ -850,110 +1558,101 @@ impl NetFacade {
             .register(&mut socket, UDP_SOCKET, Interest::READABLE)
             .unwrap();
 
-        socket
-            .connect(
-                format!("127.0.0.1:{}", networking::HOST_FRAME_STREAM_PORT)
-                    .parse()
-                    .unwrap(),
-            )
-            .unwrap();
+        //  bound to IPv4, so it can only reach the host that way
+        let host_addr = format!("{}:{}", host, networking::HOST_FRAME_STREAM_PORT)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.find(|a| a.is_ipv4()))
+            .unwrap_or_else(|| panic!("[C] failed resolving host {}", host));
+        socket.connect(host_addr).unwrap();
 
         // Initialize a buffer for the UDP packet. We use the maximum size of a UDP
         // packet, which is the maximum value of 16 a bit integer.
-        let mut buf = [0; 1 << 16];
+        let buf = [0; 1 << 16];
 
-        return Self {
+        Self {
             events,
             poll,
             socket,
             buf,
-        };
+            reassembler: networking::fragments::Reassembler::new(REASSEMBLY_TIMEOUT),
+            last_report: Instant::now(),
+        }
     }
 
-    fn get_frame(&mut self) -> SnowFrame {
-        // Poll to check if we have events waiting for us.
-        self.poll.poll(&mut self.events, None).unwrap();
-
-        // Process each event.
-        for event in self.events.iter() {
-            // Validate the token we registered our socket with,
-            // in this example it will only ever be one but we
-            // make sure it's valid none the less.
-            match event.token() {
-                UDP_SOCKET => {
-                    loop {
-                        match self.socket.recv_from(&mut self.buf) {
-                            Ok((packet_size, source_address)) => {
-                                let message = self.buf.to_vec();
-
-                                let d = mozjpeg::Decompress::with_markers(mozjpeg::NO_MARKERS)
-                                    .from_mem(&message)
-                                    .unwrap();
-
-                                assert!(d.color_space() == mozjpeg::ColorSpace::JCS_YCbCr);
-
-                                let mut rgb = d.rgba().unwrap();
-                                assert!(rgb.color_space() == mozjpeg::ColorSpace::JCS_EXT_RGBA);
-
-                                let width = rgb.width() as u32;
-                                let height = rgb.height() as u32;
-
-                                let mut pixels: Vec<u32> = rgb.read_scanlines().unwrap();
-                                assert!(rgb.finish_decompress());
-
-                                let mut u8slice = bincode::serialize(&pixels).unwrap();
-                                for i in 0..8 {
-                                    u8slice.pop();
-                                }
-
-                                return SnowFrame {
-                                    data: Some(u8slice),
-                                    width,
-                                    height,
-                                };
-                            }
-                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                                // If we get a `WouldBlock` error we know our socket
-                                // has no more packets queued, so we can return to
-                                // polling and wait for some more.
-
-                                println!("{}", e);
-                                return SnowFrame {
-                                    data: None,
-                                    width: 0,
-                                    height: 0,
-                                };
-                            }
-                            Err(e) => {
-                                // If it was any other kind of error, something went
-                                // wrong and we terminate with an error.
-                                println!("{}", e);
-                                return SnowFrame {
-                                    data: None,
-                                    width: 0,
-                                    height: 0,
-                                };
-                            }
-                        }
+    /**
+     * hands complete packets to `packets` until the render thread goes away
+     */
+    fn run(mut self, packets: crossbeam_channel::Sender<codec::Packet>) {
+        //  the poll is edge triggered, so drain the socket before
+        //  going back to it, fragments left queued wouldn't wake us up
+        loop {
+            if self.last_report.elapsed() >= networking::feedback::REPORT_INTERVAL {
+                self.report();
+            }
+
+            match self.socket.recv_from(&mut self.buf) {
+                Ok((packet_size, _)) => {
+                    let now = Instant::now();
+                    let fragment =
+                        networking::fragments::Fragment::from_bytes(&self.buf[..packet_size]);
+
+                    let ready = fragment.map_or(vec![], |f| self.reassembler.push(f, now));
+                    if ready.into_iter().any(|p| packets.send(p).is_err()) {
+                        return;
                     }
                 }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    let ready = self.reassembler.expire(Instant::now());
+                    if ready.into_iter().any(|p| packets.send(p).is_err()) {
+                        return;
+                    }
+                    let discarded = self.reassembler.take_discarded();
+                    if discarded > 0 {
+                        println!("[C] dropped {} incomplete frames", discarded);
+                    }
+                    self.nack();
 
-                _ => {
-                    println!("got token: {:?}", event);
-                    return SnowFrame {
-                        data: None,
-                        width: 0,
-                        height: 0,
-                    };
+                    //  wake up now and then even if nothing comes,
+                    //  so what's missing gets asked for and half received frames time out
+                    self.poll
+                        .poll(&mut self.events, Some(networking::fragments::NACK_DELAY))
+                        .unwrap();
+                }
+                Err(e) => {
+                    // If it was any other kind of error, something went
+                    // wrong and we terminate with an error.
+                    println!("{}", e);
+                    return;
                 }
             }
         }
+    }
 
-        return SnowFrame {
-            data: None,
-            width: 0,
-            height: 0,
-        };
+    /**
+     * asks the host to resend the fragments that went missing,
+     * before an inter-frame decoder has to do without them
+     */
+    fn nack(&mut self) {
+        for (frame_id, indexes) in self.reassembler.nacks(Instant::now()) {
+            let nack = networking::feedback::Feedback::Nack { frame_id, indexes };
+            if let Err(e) = self.socket.send(&nack.to_bytes()) {
+                println!("[C] failed sending nack: {}", e);
+            }
+        }
+    }
+
+    /**
+     * tells the host how much got lost and how late frames are,
+     * it sizes its FEC and picks its bitrate by that
+     */
+    fn report(&mut self) {
+        self.last_report = Instant::now();
+
+        let report = self.reassembler.take_report();
+        if let Err(e) = self.socket.send(&report.to_bytes()) {
+            println!("[C] failed sending report: {}", e);
+        }
     }
 }
 
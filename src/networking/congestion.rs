@@ -0,0 +1,439 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/**
+ * bounds for what congestion control may pick, it starts at `EncoderSettings::bitrate_kbps`
+ */
+#[derive(Clone, Debug)]
+pub struct CongestionSettings {
+    pub enabled: bool,
+    pub min_kbps: u32,
+    pub max_kbps: u32,
+}
+
+impl Default for CongestionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_kbps: 300,
+            max_kbps: 50_000,
+        }
+    }
+}
+
+/**
+ * the bitrate the sender wants, read by the encoder threads
+ */
+#[derive(Clone)]
+pub struct TargetBitrate(Arc<AtomicU32>);
+
+impl TargetBitrate {
+    pub fn new(kbps: u32) -> Self {
+        Self(Arc::new(AtomicU32::new(kbps)))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, kbps: u32) {
+        self.0.store(kbps, Ordering::Relaxed)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Usage {
+    Normal,
+
+    /**
+     * packets queue up somewhere between us and the client
+     */
+    Over,
+
+    /**
+     * a queue is draining, leave it be until it's empty
+     */
+    Under,
+}
+
+/**
+ * how fast the one way delay grows, from the slope of a line fitted through
+ * the last `WINDOW` smoothed accumulated delay variations
+ */
+struct Trendline {
+    samples: VecDeque<(f64, f64)>,
+    accumulated: f64,
+    smoothed: f64,
+
+    /**
+     * client clock of the last arrival, in ms since the first
+     */
+    arrival_ms: f64,
+    deltas: u32,
+}
+
+impl Trendline {
+    const WINDOW: usize = 20;
+    const SMOOTHING: f64 = 0.9;
+    const GAIN: f64 = 4.0;
+
+    fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(Self::WINDOW + 1),
+            accumulated: 0.0,
+            smoothed: 0.0,
+            arrival_ms: 0.0,
+            deltas: 0,
+        }
+    }
+
+    /**
+     * `None` until the window is full
+     */
+    fn update(&mut self, delay_variation_ms: f64, arrival_delta_ms: f64) -> Option<f64> {
+        self.deltas = (self.deltas + 1).min(1000);
+        self.arrival_ms += arrival_delta_ms;
+        self.accumulated += delay_variation_ms;
+        self.smoothed =
+            Self::SMOOTHING * self.smoothed + (1.0 - Self::SMOOTHING) * self.accumulated;
+
+        self.samples.push_back((self.arrival_ms, self.smoothed));
+        if self.samples.len() > Self::WINDOW {
+            self.samples.pop_front();
+        }
+        if self.samples.len() < Self::WINDOW {
+            return None;
+        }
+
+        let n = self.samples.len() as f64;
+        let mean_x = self.samples.iter().map(|s| s.0).sum::<f64>() / n;
+        let mean_y = self.samples.iter().map(|s| s.1).sum::<f64>() / n;
+        let (mut numerator, mut denominator) = (0.0, 0.0);
+        for (x, y) in &self.samples {
+            numerator += (x - mean_x) * (y - mean_y);
+            denominator += (x - mean_x) * (x - mean_x);
+        }
+        if denominator == 0.0 {
+            return None;
+        }
+
+        Some(numerator / denominator * self.deltas.min(60) as f64 * Self::GAIN)
+    }
+}
+
+/**
+ * compares the trend against a threshold that adapts, so it isn't
+ * set off by jitter but also doesn't lose out to TCP flows
+ */
+struct OveruseDetector {
+    threshold: f64,
+    over_ms: f64,
+    over_count: u32,
+    last_trend: f64,
+}
+
+impl OveruseDetector {
+    const OVERUSE_MS: f64 = 10.0;
+    const K_UP: f64 = 0.0087;
+    const K_DOWN: f64 = 0.039;
+
+    fn new() -> Self {
+        Self {
+            threshold: 12.5,
+            over_ms: 0.0,
+            over_count: 0,
+            last_trend: 0.0,
+        }
+    }
+
+    fn detect(&mut self, trend: f64, elapsed_ms: f64) -> Usage {
+        let usage = if trend > self.threshold {
+            self.over_ms += elapsed_ms;
+            self.over_count += 1;
+            if self.over_ms > Self::OVERUSE_MS && self.over_count > 1 && trend >= self.last_trend {
+                self.over_ms = 0.0;
+                self.over_count = 0;
+                Usage::Over
+            } else {
+                Usage::Normal
+            }
+        } else if trend < -self.threshold {
+            self.over_ms = 0.0;
+            self.over_count = 0;
+            Usage::Under
+        } else {
+            self.over_ms = 0.0;
+            self.over_count = 0;
+            Usage::Normal
+        };
+        self.last_trend = trend;
+
+        //  spikes way past the threshold are outliers, don't chase them
+        let excess = trend.abs() - self.threshold;
+        if excess < 15.0 {
+            let k = if trend.abs() < self.threshold {
+                Self::K_DOWN
+            } else {
+                Self::K_UP
+            };
+            self.threshold += k * excess * elapsed_ms.min(100.0);
+            self.threshold = self.threshold.max(6.0).min(600.0);
+        }
+
+        usage
+    }
+}
+
+/**
+ * frames we still might get an arrival time for
+ */
+const SENT_HISTORY: usize = 256;
+
+/**
+ * picks a bitrate for one client from its receiver reports, the way GCC does
+ *
+ * the delay based half watches the one way delay between frames: growing
+ * means a queue is building, so it drops to a bit under what actually got through,
+ * otherwise it creeps up. the loss based half backs off over 10% loss and
+ * creeps up under 2%. whichever is lower wins
+ */
+pub struct BandwidthEstimator {
+    settings: CongestionSettings,
+
+    /**
+     * when the last fragment of each recent frame went out
+     */
+    sent: VecDeque<(u32, Instant)>,
+
+    /**
+     * send time and client arrival time of the last frame we looked at
+     */
+    last: Option<(Instant, u32)>,
+    trendline: Trendline,
+    detector: OveruseDetector,
+    usage: Usage,
+
+    delay_based: f64,
+    loss_based: f64,
+
+    /**
+     * what the client actually received, smoothed
+     */
+    incoming_kbps: f64,
+    last_report: Option<Instant>,
+    last_decrease: Option<Instant>,
+
+    /**
+     * last time we sent it something or heard from it
+     */
+    last_active: Instant,
+}
+
+impl BandwidthEstimator {
+    /**
+     * one decrease per this long, the last one has to show first
+     */
+    const DECREASE_INTERVAL: Duration = Duration::from_millis(200);
+
+    pub fn new(settings: &CongestionSettings, start_kbps: u32) -> Self {
+        let start = start_kbps.max(settings.min_kbps).min(settings.max_kbps) as f64;
+
+        Self {
+            settings: settings.clone(),
+            sent: VecDeque::with_capacity(SENT_HISTORY),
+            last: None,
+            trendline: Trendline::new(),
+            detector: OveruseDetector::new(),
+            usage: Usage::Normal,
+            delay_based: start,
+            loss_based: start,
+            incoming_kbps: 0.0,
+            last_report: None,
+            last_decrease: None,
+            last_active: Instant::now(),
+        }
+    }
+
+    /**
+     * the last fragment of `frame_id` just went out
+     */
+    pub fn sent(&mut self, frame_id: u32, now: Instant) {
+        if self.sent.len() >= SENT_HISTORY {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((frame_id, now));
+        self.last_active = now;
+    }
+
+    /**
+     * a receiver report, `arrivals` are frame ids and when the client
+     * had them complete in µs on its own clock
+     */
+    pub fn report(
+        &mut self,
+        expected: u32,
+        arrived: u32,
+        bytes: u32,
+        arrivals: &[(u32, u32)],
+        now: Instant,
+    ) {
+        if let Some(last) = self.last_report {
+            let elapsed = now.duration_since(last).as_secs_f64();
+            if elapsed > 0.0 {
+                let kbps = bytes as f64 * 8.0 / 1000.0 / elapsed;
+                self.incoming_kbps = if self.incoming_kbps == 0.0 {
+                    kbps
+                } else {
+                    self.incoming_kbps * 0.7 + kbps * 0.3
+                };
+            }
+        }
+        let elapsed = self
+            .last_report
+            .map(|l| now.duration_since(l).as_secs_f64())
+            .unwrap_or(0.0);
+        self.last_report = Some(now);
+        self.last_active = now;
+
+        for &(frame_id, arrival_us) in arrivals {
+            let sent = match self.sent.iter().find(|(id, _)| *id == frame_id) {
+                Some((_, sent)) => *sent,
+                None => continue,
+            };
+
+            if let Some((last_sent, last_arrival)) = self.last {
+                if sent > last_sent {
+                    let send_delta = sent.duration_since(last_sent).as_secs_f64() * 1000.0;
+                    let arrival_delta =
+                        arrival_us.wrapping_sub(last_arrival) as i32 as f64 / 1000.0;
+
+                    if let Some(trend) = self
+                        .trendline
+                        .update(arrival_delta - send_delta, arrival_delta)
+                    {
+                        self.usage = self.detector.detect(trend, send_delta);
+                    }
+                }
+            }
+            self.last = Some((sent, arrival_us));
+        }
+
+        self.update_delay_based(elapsed, now);
+        self.update_loss_based(expected, arrived);
+    }
+
+    fn update_delay_based(&mut self, elapsed: f64, now: Instant) {
+        match self.usage {
+            Usage::Over => {
+                let recently = self
+                    .last_decrease
+                    .map(|d| now.duration_since(d) < Self::DECREASE_INTERVAL)
+                    .unwrap_or(false);
+                if !recently {
+                    let through = if self.incoming_kbps > 0.0 {
+                        self.incoming_kbps
+                    } else {
+                        self.delay_based
+                    };
+                    self.delay_based = 0.85 * through;
+                    self.last_decrease = Some(now);
+                }
+            }
+            Usage::Under => {}
+            Usage::Normal => {
+                self.delay_based *= 1.08f64.powf(elapsed.min(1.0));
+
+                //  no point aiming far past what the client gets
+                if self.incoming_kbps > 0.0 {
+                    self.delay_based = self.delay_based.min(1.5 * self.incoming_kbps + 100.0);
+                }
+            }
+        }
+
+        self.delay_based = self.clamp(self.delay_based);
+    }
+
+    fn update_loss_based(&mut self, expected: u32, arrived: u32) {
+        if expected == 0 {
+            return;
+        }
+
+        let loss = 1.0 - arrived.min(expected) as f64 / expected as f64;
+        if loss > 0.1 {
+            self.loss_based *= 1.0 - 0.5 * loss;
+        } else if loss < 0.02 {
+            self.loss_based *= 1.05;
+        }
+
+        self.loss_based = self.clamp(self.loss_based);
+    }
+
+    fn clamp(&self, kbps: f64) -> f64 {
+        kbps.max(self.settings.min_kbps as f64)
+            .min(self.settings.max_kbps as f64)
+    }
+
+    pub fn target_kbps(&self) -> u32 {
+        self.delay_based.min(self.loss_based) as u32
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    pub fn idle(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * frames every 10ms, each taking `extra_ms` longer to arrive than the last
+     */
+    fn feed(estimator: &mut BandwidthEstimator, start: Instant, frames: u32, extra_ms: f64) {
+        let mut arrival = 0.0;
+        for i in 0..frames {
+            let sent = start + Duration::from_millis(10 * i as u64);
+            estimator.sent(i, sent);
+            arrival += 10.0 + extra_ms;
+            estimator.report(10, 10, 5000, &[(i, (arrival * 1000.0) as u32)], sent);
+        }
+    }
+
+    #[test]
+    fn growing_delay_backs_off() {
+        let settings = CongestionSettings::default();
+        let mut estimator = BandwidthEstimator::new(&settings, 8000);
+        feed(&mut estimator, Instant::now(), 100, 2.0);
+
+        assert_eq!(estimator.usage(), Usage::Over);
+        assert!(estimator.target_kbps() < 8000);
+    }
+
+    #[test]
+    fn steady_delay_ramps_up() {
+        let settings = CongestionSettings::default();
+        let mut estimator = BandwidthEstimator::new(&settings, 2000);
+        feed(&mut estimator, Instant::now(), 100, 0.0);
+
+        assert_eq!(estimator.usage(), Usage::Normal);
+        assert!(estimator.target_kbps() > 2000);
+    }
+
+    #[test]
+    fn heavy_loss_backs_off() {
+        let settings = CongestionSettings::default();
+        let mut estimator = BandwidthEstimator::new(&settings, 8000);
+        let now = Instant::now();
+        for i in 0..5 {
+            estimator.report(100, 70, 0, &[], now + Duration::from_millis(100 * i));
+        }
+
+        assert!(estimator.target_kbps() < 4000);
+    }
+}
@@ -0,0 +1,199 @@
+use super::fragments::Fragment;
+
+/**
+ * a parity fragment starts with the XOR of its group's payload lengths,
+ * so a short last fragment comes back the right size
+ */
+pub const PARITY_OVERHEAD: usize = 2;
+
+/**
+ * how much redundancy the host adds to every frame's fragments
+ *
+ * the ratio is parity over data fragments, it starts at `min_ratio`
+ * and follows the loss clients report, but never past `max_ratio`
+ */
+#[derive(Clone, Debug)]
+pub struct FecSettings {
+    pub enabled: bool,
+    pub min_ratio: f32,
+    pub max_ratio: f32,
+}
+
+impl Default for FecSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_ratio: 0.1,
+            max_ratio: 0.5,
+        }
+    }
+}
+
+/**
+ * adds one XOR parity fragment after every `group` data fragments,
+ * any one of them that goes missing can be rebuilt from the rest
+ */
+pub fn protect(fragments: &[Fragment], group: u8) -> Vec<Fragment> {
+    let group = group.max(1);
+    let mut protected = Vec::with_capacity(fragments.len() + fragments.len() / group as usize + 1);
+
+    for (index, chunk) in fragments.chunks(group as usize).enumerate() {
+        for fragment in chunk {
+            protected.push(Fragment {
+                group,
+                ..fragment.clone()
+            });
+        }
+
+        let payloads: Vec<&[u8]> = chunk.iter().map(|f| &f.payload[..]).collect();
+        protected.push(Fragment {
+            index: index as u16,
+            parity: true,
+            group,
+            payload: parity(&payloads),
+            ..chunk[0].clone()
+        });
+    }
+
+    protected
+}
+
+fn parity(payloads: &[&[u8]]) -> Vec<u8> {
+    let longest = payloads.iter().map(|p| p.len()).max().unwrap_or(0);
+    let mut parity = vec![0; PARITY_OVERHEAD + longest];
+
+    let mut lengths = 0u16;
+    for payload in payloads {
+        lengths ^= payload.len() as u16;
+        for (p, b) in parity[PARITY_OVERHEAD..].iter_mut().zip(payload.iter()) {
+            *p ^= b;
+        }
+    }
+    parity[..PARITY_OVERHEAD].copy_from_slice(&lengths.to_le_bytes());
+
+    parity
+}
+
+/**
+ * the one payload of a group that isn't in `present`
+ */
+pub fn recover(parity: &[u8], present: &[&[u8]]) -> Option<Vec<u8>> {
+    if parity.len() < PARITY_OVERHEAD {
+        return None;
+    }
+
+    let mut lengths = u16::from_le_bytes([parity[0], parity[1]]);
+    let mut payload = parity[PARITY_OVERHEAD..].to_vec();
+    for p in present {
+        lengths ^= p.len() as u16;
+        for (b, x) in payload.iter_mut().zip(p.iter()) {
+            *b ^= x;
+        }
+    }
+
+    if lengths as usize > payload.len() {
+        return None;
+    }
+    payload.truncate(lengths as usize);
+
+    Some(payload)
+}
+
+/**
+ * how much parity one client gets, from the loss it reports
+ */
+pub struct Redundancy {
+    settings: FecSettings,
+
+    /**
+     * smoothed share of datagrams that don't make it
+     */
+    loss: f32,
+}
+
+impl Redundancy {
+    pub fn new(settings: &FecSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            loss: 0.0,
+        }
+    }
+
+    /**
+     * `arrived` out of `expected` datagrams got to the client
+     */
+    pub fn report(&mut self, expected: u32, arrived: u32) {
+        if expected == 0 {
+            return;
+        }
+
+        let loss = 1.0 - arrived.min(expected) as f32 / expected as f32;
+        self.loss = self.loss * 0.7 + loss * 0.3;
+    }
+
+    pub fn loss(&self) -> f32 {
+        self.loss
+    }
+
+    /**
+     * one parity fragment fixes one loss per group, aim for
+     * groups small enough that two losses in one are rare
+     */
+    pub fn ratio(&self) -> f32 {
+        (self.loss * 2.0)
+            .max(self.settings.min_ratio)
+            .min(self.settings.max_ratio)
+    }
+
+    /**
+     * data fragments per parity fragment
+     */
+    pub fn group(&self) -> u8 {
+        (1.0 / self.ratio().max(1.0 / 255.0))
+            .round()
+            .max(1.0)
+            .min(255.0) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_one_payload_comes_back() {
+        let payloads: Vec<Vec<u8>> = vec![
+            vec![1; 10],
+            vec![2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            vec![42; 3],
+        ];
+        let all: Vec<&[u8]> = payloads.iter().map(|p| &p[..]).collect();
+        let parity = parity(&all);
+
+        for missing in 0..payloads.len() {
+            let present: Vec<&[u8]> = all
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != missing)
+                .map(|(_, p)| *p)
+                .collect();
+            assert_eq!(recover(&parity, &present), Some(payloads[missing].clone()));
+        }
+    }
+
+    #[test]
+    fn redundancy_follows_loss() {
+        let mut redundancy = Redundancy::new(&FecSettings::default());
+        assert_eq!(redundancy.group(), 10);
+
+        for _ in 0..20 {
+            redundancy.report(100, 85);
+        }
+        assert_eq!(redundancy.group(), 3);
+
+        for _ in 0..20 {
+            redundancy.report(100, 20);
+        }
+        assert_eq!(redundancy.group(), 2);
+    }
+}
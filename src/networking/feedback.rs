@@ -0,0 +1,124 @@
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+/**
+ * how often the client tells the host how the stream is going,
+ * congestion control can't react any quicker than this
+ */
+pub const REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/**
+ * what the client sends back to the host over the frame socket
+ *
+ * the first byte says which one it is, integers are little endian
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Feedback {
+    /**
+     * `arrived` out of `expected` datagrams made it since the last report,
+     * `bytes` of them, and when each frame in `arrivals` came out complete,
+     * frame id and µs on the client's clock
+     */
+    Report {
+        expected: u32,
+        arrived: u32,
+        bytes: u32,
+        arrivals: Vec<(u32, u32)>,
+    },
+
+    /**
+     * data fragments of `frame_id` that haven't shown up, please resend,
+     * no indexes if none of them did
+     */
+    Nack { frame_id: u32, indexes: Vec<u16> },
+}
+
+const REPORT: u8 = 0;
+const NACK: u8 = 1;
+
+/**
+ * as many arrivals as fit in one datagram after the counts
+ */
+pub const MAX_ARRIVALS: usize = (super::fragments::MAX_DATAGRAM - 15) / 8;
+
+/**
+ * as many indexes as fit in one datagram with the tag, frame id and count
+ */
+pub const MAX_NACKED: usize = (super::fragments::MAX_DATAGRAM - 7) / 2;
+
+impl Feedback {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        match self {
+            Feedback::Report {
+                expected,
+                arrived,
+                bytes: received,
+                arrivals,
+            } => {
+                //  the newest ones matter most
+                let arrivals = &arrivals[arrivals.len().saturating_sub(MAX_ARRIVALS)..];
+                bytes.write_u8(REPORT).unwrap();
+                bytes.write_u32::<LittleEndian>(*expected).unwrap();
+                bytes.write_u32::<LittleEndian>(*arrived).unwrap();
+                bytes.write_u32::<LittleEndian>(*received).unwrap();
+                bytes
+                    .write_u16::<LittleEndian>(arrivals.len() as u16)
+                    .unwrap();
+                for (frame_id, at) in arrivals {
+                    bytes.write_u32::<LittleEndian>(*frame_id).unwrap();
+                    bytes.write_u32::<LittleEndian>(*at).unwrap();
+                }
+            }
+            Feedback::Nack { frame_id, indexes } => {
+                let indexes = &indexes[..indexes.len().min(MAX_NACKED)];
+                bytes.write_u8(NACK).unwrap();
+                bytes.write_u32::<LittleEndian>(*frame_id).unwrap();
+                bytes
+                    .write_u16::<LittleEndian>(indexes.len() as u16)
+                    .unwrap();
+                for index in indexes {
+                    bytes.write_u16::<LittleEndian>(*index).unwrap();
+                }
+            }
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(bytes);
+        match reader.read_u8().ok()? {
+            REPORT => {
+                let expected = reader.read_u32::<LittleEndian>().ok()?;
+                let arrived = reader.read_u32::<LittleEndian>().ok()?;
+                let bytes = reader.read_u32::<LittleEndian>().ok()?;
+                let count = reader.read_u16::<LittleEndian>().ok()?;
+                let arrivals = (0..count)
+                    .map(|_| {
+                        Some((
+                            reader.read_u32::<LittleEndian>().ok()?,
+                            reader.read_u32::<LittleEndian>().ok()?,
+                        ))
+                    })
+                    .collect::<Option<Vec<(u32, u32)>>>()?;
+                Some(Feedback::Report {
+                    expected,
+                    arrived,
+                    bytes,
+                    arrivals,
+                })
+            }
+            NACK => {
+                let frame_id = reader.read_u32::<LittleEndian>().ok()?;
+                let count = reader.read_u16::<LittleEndian>().ok()?;
+                let indexes = (0..count)
+                    .map(|_| reader.read_u16::<LittleEndian>().ok())
+                    .collect::<Option<Vec<u16>>>()?;
+                Some(Feedback::Nack { frame_id, indexes })
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,798 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::fec;
+use super::feedback::Feedback;
+use crate::codec::{CodecId, Packet};
+
+/**
+ * biggest datagram we send, leaves room for IP and UDP headers
+ * (and a tunnel or two) under a 1500 byte MTU
+ */
+pub const MAX_DATAGRAM: usize = 1200;
+
+/**
+ * frame id: u32, fragment index: u16, fragment count: u16, codec: u8, flags: u8,
+ * fec group: u8, integers are little endian
+ */
+pub const HEADER_LEN: usize = 11;
+
+/**
+ * parity fragments carry the XORed payload lengths on top, see `fec`
+ */
+pub const MAX_PAYLOAD: usize = MAX_DATAGRAM - HEADER_LEN - fec::PARITY_OVERHEAD;
+
+const KEYFRAME: u8 = 1;
+const PARITY: u8 = 2;
+
+/**
+ * one datagram's worth of a frame
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fragment {
+    pub frame_id: u32,
+
+    /**
+     * which group it protects if it's parity
+     */
+    pub index: u16,
+
+    /**
+     * data fragments in the frame, parity isn't counted
+     */
+    pub count: u16,
+    pub codec: CodecId,
+    pub keyframe: bool,
+    pub parity: bool,
+
+    /**
+     * how many data fragments share one parity fragment, 0 without FEC
+     */
+    pub group: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Fragment {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        bytes.write_u32::<LittleEndian>(self.frame_id).unwrap();
+        bytes.write_u16::<LittleEndian>(self.index).unwrap();
+        bytes.write_u16::<LittleEndian>(self.count).unwrap();
+        bytes.write_u8(self.codec.as_u8()).unwrap();
+        let mut flags = 0;
+        if self.keyframe {
+            flags |= KEYFRAME;
+        }
+        if self.parity {
+            flags |= PARITY;
+        }
+        bytes.write_u8(flags).unwrap();
+        bytes.write_u8(self.group).unwrap();
+        bytes.extend_from_slice(&self.payload);
+
+        bytes
+    }
+
+    /**
+     * `None` if it's too short, from a codec we don't know or its index is out of range
+     */
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(bytes);
+        let frame_id = reader.read_u32::<LittleEndian>().ok()?;
+        let index = reader.read_u16::<LittleEndian>().ok()?;
+        let count = reader.read_u16::<LittleEndian>().ok()?;
+        let codec = CodecId::from_u8(reader.read_u8().ok()?)?;
+        let flags = reader.read_u8().ok()?;
+        let group = reader.read_u8().ok()?;
+        let parity = flags & PARITY != 0;
+        if index >= count || (parity && group == 0) {
+            return None;
+        }
+
+        Some(Self {
+            frame_id,
+            index,
+            count,
+            codec,
+            keyframe: flags & KEYFRAME != 0,
+            parity,
+            group,
+            payload: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/**
+ * cuts an encoded packet into fragments that each fit in one datagram
+ *
+ * panics past 65535 fragments, which is ~78MB and no frame gets there
+ */
+pub fn split(frame_id: u32, packet: &Packet) -> Vec<Fragment> {
+    let bytes = packet.to_bytes();
+    let chunks: Vec<&[u8]> = bytes.chunks(MAX_PAYLOAD).collect();
+    assert!(
+        chunks.len() <= u16::MAX as usize,
+        "frame too big to fragment"
+    );
+
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| Fragment {
+            frame_id,
+            index: index as u16,
+            count: chunks.len() as u16,
+            codec: packet.codec,
+            keyframe: packet.keyframe,
+            parity: false,
+            group: 0,
+            payload: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/**
+ * true if frame `a` comes after frame `b`, ids wrap around
+ */
+pub fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/**
+ * how long a frame has to sit without news before we ask for what's missing
+ */
+pub const NACK_DELAY: Duration = Duration::from_millis(20);
+
+const MAX_NACKS: u32 = 3;
+
+/**
+ * past this many frames missing in a row we don't ask for them one by one,
+ * the decoder is better off waiting for a keyframe
+ */
+const MAX_GAP: u32 = 64;
+
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    parity: Vec<Option<Vec<u8>>>,
+    group: u8,
+
+    /**
+     * data fragments we have, received or recovered
+     */
+    received: usize,
+
+    /**
+     * datagrams that made it, data and parity
+     */
+    arrived: u32,
+    started: Instant,
+    last_arrival: Instant,
+
+    /**
+     * when we last asked for what's missing, and how often we did
+     */
+    nacked: Option<Instant>,
+    nacks: u32,
+}
+
+impl Partial {
+    fn new(fragment: &Fragment, now: Instant) -> Self {
+        let groups = if fragment.group == 0 {
+            0
+        } else {
+            (fragment.count as usize + fragment.group as usize - 1) / fragment.group as usize
+        };
+
+        Self {
+            fragments: vec![None; fragment.count as usize],
+            parity: vec![None; groups],
+            group: fragment.group,
+            received: 0,
+            arrived: 0,
+            started: now,
+            last_arrival: now,
+            nacked: None,
+            nacks: 0,
+        }
+    }
+
+    fn expected(&self) -> u32 {
+        (self.fragments.len() + self.parity.len()) as u32
+    }
+
+    /**
+     * fills in the data fragment missing from `group`,
+     * if it's the only one and the group's parity made it
+     */
+    fn recover(&mut self, group: usize) {
+        let size = self.group as usize;
+        let range = group * size..((group + 1) * size).min(self.fragments.len());
+
+        let mut missing = range.clone().filter(|&i| self.fragments[i].is_none());
+        let index = match (missing.next(), missing.next()) {
+            (Some(index), None) => index,
+            _ => return,
+        };
+        let parity = match &self.parity[group] {
+            Some(parity) => parity,
+            None => return,
+        };
+
+        let present: Vec<&[u8]> = range.filter_map(|i| self.fragments[i].as_deref()).collect();
+        if let Some(payload) = fec::recover(parity, &present) {
+            self.fragments[index] = Some(payload);
+            self.received += 1;
+        }
+    }
+}
+
+/**
+ * a frame none of whose fragments showed up, we only know
+ * it's missing because a newer one did
+ */
+struct Gap {
+    started: Instant,
+    nacked: Option<Instant>,
+    nacks: u32,
+}
+
+/**
+ * puts fragments back together into packets on the client
+ *
+ * a lost data fragment is rebuilt from its group's parity if it has one,
+ * frames that still don't complete within `timeout`, or that are older
+ * than one we already handed out, are thrown away
+ *
+ * intra-only frames come out as soon as they're complete, anything older
+ * still missing pieces is given up on, inter frames come out in order,
+ * a complete one waits while an older one can still be resent
+ */
+pub struct Reassembler {
+    partial: HashMap<u32, Partial>,
+    timeout: Duration,
+
+    /**
+     * frames we haven't seen a single fragment of
+     */
+    gaps: HashMap<u32, Gap>,
+
+    /**
+     * complete inter frames waiting for an older one
+     */
+    held: HashMap<u32, Packet>,
+
+    /**
+     * the newest frame any fragment was for
+     */
+    seen: Option<u32>,
+
+    /**
+     * the last frame that came out complete
+     */
+    newest: Option<u32>,
+
+    /**
+     * incomplete frames thrown away since the last `take_discarded`
+     */
+    discarded: u64,
+
+    /**
+     * datagrams sent and received for frames we're done with,
+     * and bytes received, since the last `take_report`
+     */
+    expected: u32,
+    arrived: u32,
+    bytes: u32,
+
+    /**
+     * frames that came out complete since the last `take_report`, and when
+     */
+    arrivals: Vec<(u32, u32)>,
+
+    /**
+     * what arrival times count from
+     */
+    epoch: Instant,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            partial: HashMap::new(),
+            timeout,
+            gaps: HashMap::new(),
+            held: HashMap::new(),
+            seen: None,
+            newest: None,
+            discarded: 0,
+            expected: 0,
+            arrived: 0,
+            bytes: 0,
+            arrivals: vec![],
+            epoch: Instant::now(),
+        }
+    }
+
+    /**
+     * the packets that can go to the decoder once this fragment is in,
+     * its own if it was the last one missing and nothing older holds it up,
+     * and any newer ones that were waiting for it
+     *
+     * `now` is when the datagram came off the socket, the host reads
+     * queueing delay from it
+     */
+    pub fn push(&mut self, fragment: Fragment, now: Instant) -> Vec<Packet> {
+        let id = fragment.frame_id;
+        let intra = fragment.codec.is_intra_only();
+        self.bytes = self
+            .bytes
+            .wrapping_add((HEADER_LEN + fragment.payload.len()) as u32);
+        if let Some(newest) = self.newest {
+            //  parity for a frame that was done without it still counts as arrived
+            if id == newest {
+                self.arrived += 1;
+            }
+            if !is_newer(id, newest) {
+                return vec![];
+            }
+        }
+        if self.held.contains_key(&id) {
+            self.arrived += 1;
+            return vec![];
+        }
+
+        self.gaps.remove(&id);
+        match self.seen {
+            Some(seen) if is_newer(id, seen) => {
+                let skipped = id.wrapping_sub(seen) - 1;
+                if skipped <= MAX_GAP {
+                    for missing in (1..=skipped).map(|i| seen.wrapping_add(i)) {
+                        self.gaps.insert(
+                            missing,
+                            Gap {
+                                started: now,
+                                nacked: None,
+                                nacks: 0,
+                            },
+                        );
+                    }
+                }
+                self.seen = Some(id);
+            }
+            None => self.seen = Some(id),
+            _ => {}
+        }
+
+        let partial = self
+            .partial
+            .entry(id)
+            .or_insert_with(|| Partial::new(&fragment, now));
+        if partial.fragments.len() != fragment.count as usize || partial.group != fragment.group {
+            return vec![];
+        }
+
+        partial.last_arrival = now;
+        let index = fragment.index as usize;
+        if fragment.parity {
+            match partial.parity.get_mut(index) {
+                Some(slot) => *slot = Some(fragment.payload),
+                None => return vec![],
+            }
+            partial.arrived += 1;
+            partial.recover(index);
+        } else {
+            let slot = &mut partial.fragments[index];
+            partial.arrived += 1;
+            if slot.is_none() {
+                *slot = Some(fragment.payload);
+                partial.received += 1;
+                if partial.group != 0 {
+                    partial.recover(index / partial.group as usize);
+                }
+            }
+        }
+        if partial.received < partial.fragments.len() {
+            return vec![];
+        }
+
+        let partial = match self.partial.remove(&id) {
+            Some(partial) => partial,
+            None => return vec![],
+        };
+        self.count(&partial);
+        let bytes: Vec<u8> = partial.fragments.into_iter().flatten().flatten().collect();
+        let at = now.saturating_duration_since(self.epoch).as_micros() as u32;
+        self.arrivals.push((id, at));
+        let packet = Packet::from_bytes(&bytes);
+
+        //  a keyframe doesn't need anything older either
+        if intra || packet.as_ref().is_some_and(|p| p.keyframe) {
+            //  whatever is still missing pieces from before this one is no use anymore
+            self.newest = Some(id);
+            self.drop_older(id);
+            //  newer frames that arrived first only waited on what's gone now
+            let mut released: Vec<Packet> = packet.into_iter().collect();
+            released.extend(self.release());
+            return released;
+        }
+
+        //  a broken one is just skipped once it isn't holding anything up
+        if let Some(packet) = packet {
+            self.held.insert(id, packet);
+        }
+        self.release()
+    }
+
+    /**
+     * hands out held frames oldest first, as long as
+     * nothing older is still on its way
+     */
+    fn release(&mut self) -> Vec<Packet> {
+        let mut released = vec![];
+        loop {
+            let oldest = match self
+                .held
+                .keys()
+                .copied()
+                .reduce(|a, b| if is_newer(a, b) { b } else { a })
+            {
+                Some(oldest) => oldest,
+                None => return released,
+            };
+            let waiting = self
+                .partial
+                .keys()
+                .chain(self.gaps.keys())
+                .any(|other| is_newer(oldest, *other));
+            if waiting {
+                return released;
+            }
+
+            released.extend(self.held.remove(&oldest));
+            self.newest = Some(oldest);
+        }
+    }
+
+    /**
+     * gives up on everything from before `id`
+     */
+    fn drop_older(&mut self, id: u32) {
+        let stale: Vec<u32> = self
+            .partial
+            .keys()
+            .copied()
+            .filter(|other| !is_newer(*other, id))
+            .collect();
+        self.drop_frames(&stale);
+
+        let gaps = self.gaps.len();
+        self.gaps.retain(|other, _| is_newer(*other, id));
+        self.discarded += (gaps - self.gaps.len()) as u64;
+        self.held.retain(|other, _| is_newer(*other, id));
+    }
+
+    /**
+     * throws away frames that took too long,
+     * and hands out the ones that were waiting for them
+     */
+    pub fn expire(&mut self, now: Instant) -> Vec<Packet> {
+        let timeout = self.timeout;
+        let expired: Vec<u32> = self
+            .partial
+            .iter()
+            .filter(|(_, p)| now.duration_since(p.started) >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        self.drop_frames(&expired);
+
+        let gaps = self.gaps.len();
+        self.gaps
+            .retain(|_, gap| now.duration_since(gap.started) < timeout);
+        self.discarded += (gaps - self.gaps.len()) as u64;
+
+        self.release()
+    }
+
+    /**
+     * the data fragments frames are still missing once nothing has come
+     * for them in `NACK_DELAY`, at most `MAX_NACKS` times per frame
+     *
+     * losses FEC can still make up for are asked for too,
+     * its parity would have shown up by then
+     *
+     * frames that never showed up at all are asked for with no indexes
+     */
+    pub fn nacks(&mut self, now: Instant) -> Vec<(u32, Vec<u16>)> {
+        let mut nacks = vec![];
+        for (id, partial) in self.partial.iter_mut() {
+            let since = partial
+                .nacked
+                .unwrap_or(partial.last_arrival)
+                .max(partial.last_arrival);
+            if partial.nacks >= MAX_NACKS || now.duration_since(since) < NACK_DELAY {
+                continue;
+            }
+
+            let missing: Vec<u16> = (0..partial.fragments.len())
+                .filter(|&i| partial.fragments[i].is_none())
+                .map(|i| i as u16)
+                .collect();
+            partial.nacked = Some(now);
+            partial.nacks += 1;
+            nacks.push((*id, missing));
+        }
+
+        for (id, gap) in self.gaps.iter_mut() {
+            let since = gap.nacked.unwrap_or(gap.started);
+            if gap.nacks >= MAX_NACKS || now.duration_since(since) < NACK_DELAY {
+                continue;
+            }
+
+            gap.nacked = Some(now);
+            gap.nacks += 1;
+            nacks.push((*id, vec![]));
+        }
+
+        nacks
+    }
+
+    fn drop_frames(&mut self, ids: &[u32]) {
+        for id in ids {
+            if let Some(partial) = self.partial.remove(id) {
+                self.count(&partial);
+                self.discarded += 1;
+            }
+        }
+    }
+
+    fn count(&mut self, partial: &Partial) {
+        self.expected += partial.expected();
+        self.arrived += partial.arrived.min(partial.expected());
+    }
+
+    pub fn take_discarded(&mut self) -> u64 {
+        std::mem::take(&mut self.discarded)
+    }
+
+    /**
+     * what the host needs for FEC and congestion control,
+     * about everything since the last call
+     */
+    pub fn take_report(&mut self) -> Feedback {
+        let expected = std::mem::take(&mut self.expected);
+        let arrived = std::mem::take(&mut self.arrived);
+
+        Feedback::Report {
+            expected,
+            arrived: arrived.min(expected),
+            bytes: std::mem::take(&mut self.bytes),
+            arrivals: std::mem::take(&mut self.arrivals),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(len: usize) -> Packet {
+        Packet {
+            codec: CodecId::Jpeg,
+            keyframe: true,
+            width: 4,
+            height: 2,
+            data: (0..len).map(|i| i as u8).collect(),
+        }
+    }
+
+    fn wire(fragment: &Fragment) -> Fragment {
+        Fragment::from_bytes(&fragment.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn fragments_fit_in_a_datagram() {
+        let fragments = split(7, &packet(5000));
+        assert_eq!(fragments.len(), 5);
+        assert!(fragments.iter().all(|f| f.to_bytes().len() <= MAX_DATAGRAM));
+        assert!(fragments.iter().all(|f| f.count == 5 && f.frame_id == 7));
+    }
+
+    #[test]
+    fn reassembles_in_any_order() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(100));
+        let mut fragments = split(1, &packet(3000));
+        fragments.reverse();
+
+        let last = fragments.pop().unwrap();
+        for f in &fragments {
+            assert!(reassembler.push(wire(f), now).is_empty());
+        }
+        assert_eq!(reassembler.push(wire(&last), now), vec![packet(3000)]);
+    }
+
+    #[test]
+    fn incomplete_frames_time_out() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(100));
+        let fragments = split(1, &packet(3000));
+
+        reassembler.push(wire(&fragments[0]), now);
+        reassembler.expire(now + Duration::from_millis(150));
+        assert_eq!(reassembler.take_discarded(), 1);
+
+        //  starts over, the first fragment is gone
+        for f in &fragments[1..] {
+            assert!(reassembler.push(wire(f), now).is_empty());
+        }
+    }
+
+    #[test]
+    fn older_frames_are_dropped_once_a_newer_one_is_done() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(100));
+        let old = split(1, &packet(3000));
+        let new = split(2, &packet(100));
+
+        reassembler.push(wire(&old[0]), now);
+        assert_eq!(reassembler.push(wire(&new[0]), now).len(), 1);
+        assert_eq!(reassembler.take_discarded(), 1);
+        assert!(reassembler.push(wire(&old[1]), now).is_empty());
+    }
+
+    #[test]
+    fn lost_fragments_are_recovered_from_parity() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(100));
+        let fragments = fec::protect(&split(1, &packet(5000)), 2);
+
+        //  one from the first group and the short last one, which is alone in its group
+        let mut done = vec![];
+        for f in fragments
+            .iter()
+            .filter(|f| f.parity || (f.index != 1 && f.index != 4))
+        {
+            done.extend(reassembler.push(wire(f), now));
+        }
+        assert_eq!(done, vec![packet(5000)]);
+        match reassembler.take_report() {
+            Feedback::Report {
+                expected,
+                arrived,
+                arrivals,
+                ..
+            } => {
+                assert_eq!((expected, arrived), (8, 6));
+                assert_eq!(arrivals.len(), 1);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn missing_fragments_are_nacked_after_a_while() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(200));
+        let fragments = split(1, &packet(5000));
+
+        for f in fragments.iter().filter(|f| f.index != 2) {
+            reassembler.push(wire(f), now);
+        }
+        assert!(reassembler.nacks(now).is_empty());
+
+        let later = now + NACK_DELAY;
+        assert_eq!(reassembler.nacks(later), vec![(1, vec![2])]);
+        assert!(reassembler.nacks(later).is_empty());
+
+        assert_eq!(
+            reassembler.push(wire(&fragments[2]), later),
+            vec![packet(5000)]
+        );
+    }
+
+    fn inter(len: usize) -> Packet {
+        Packet {
+            codec: CodecId::Tiles,
+            keyframe: false,
+            ..packet(len)
+        }
+    }
+
+    #[test]
+    fn inter_frames_wait_for_older_ones() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(200));
+        let old = split(1, &inter(3000));
+        let new = split(2, &inter(100));
+
+        reassembler.push(wire(&old[0]), now);
+        assert!(reassembler.push(wire(&new[0]), now).is_empty());
+
+        //  the resent rest of the old one lets both through, in order
+        reassembler.push(wire(&old[1]), now);
+        assert_eq!(
+            reassembler.push(wire(&old[2]), now),
+            vec![inter(3000), inter(100)]
+        );
+        assert_eq!(reassembler.take_discarded(), 0);
+    }
+
+    #[test]
+    fn inter_keyframes_dont_wait_for_older_ones() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(200));
+        let old = split(1, &inter(3000));
+        let key = Packet {
+            keyframe: true,
+            ..inter(100)
+        };
+
+        reassembler.push(wire(&old[0]), now);
+        assert_eq!(
+            reassembler.push(wire(&split(2, &key)[0]), now),
+            vec![key.clone()]
+        );
+        assert_eq!(reassembler.take_discarded(), 1);
+
+        //  the rest of the old one is too late now
+        assert!(reassembler.push(wire(&old[1]), now).is_empty());
+        assert!(reassembler.push(wire(&old[2]), now).is_empty());
+    }
+
+    #[test]
+    fn inter_frames_stop_waiting_once_older_ones_expire() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(100));
+        let old = split(1, &inter(3000));
+        let new = split(2, &inter(100));
+
+        reassembler.push(wire(&old[0]), now);
+        assert!(reassembler.push(wire(&new[0]), now).is_empty());
+        assert_eq!(
+            reassembler.expire(now + Duration::from_millis(150)),
+            vec![inter(100)]
+        );
+        assert_eq!(reassembler.take_discarded(), 1);
+    }
+
+    #[test]
+    fn frames_lost_entirely_are_nacked() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(200));
+
+        assert_eq!(
+            reassembler.push(wire(&split(1, &inter(100))[0]), now).len(),
+            1
+        );
+        //  2 and 3 never make it
+        assert!(reassembler
+            .push(wire(&split(4, &inter(100))[0]), now)
+            .is_empty());
+
+        let later = now + NACK_DELAY;
+        let mut nacks = reassembler.nacks(later);
+        nacks.sort();
+        assert_eq!(nacks, vec![(2, vec![]), (3, vec![])]);
+
+        //  a resent frame fills its gap, the other one still holds 4 back
+        assert_eq!(
+            reassembler
+                .push(wire(&split(2, &inter(100))[0]), later)
+                .len(),
+            1
+        );
+        assert_eq!(
+            reassembler.push(wire(&split(3, &inter(100))[0]), later),
+            vec![inter(100), inter(100)]
+        );
+    }
+
+    #[test]
+    fn ids_wrap_around() {
+        assert!(is_newer(0, u32::MAX));
+        assert!(!is_newer(u32::MAX, 0));
+    }
+}
@@ -1,9 +1,19 @@
 use std::thread;
 use std::time::Duration;
 
+use crate::codec::CodecId;
+
+pub mod congestion;
+pub mod fec;
+pub mod feedback;
+pub mod fragments;
+pub mod retransmit;
+pub mod subscribers;
+
 pub const HOST_PRIMARY_PORT: u32 = 5564;
 pub const HOST_FRAME_STREAM_PORT: u32 = 5565;
 pub const HOST_INPUT_STREAM_PORT: u32 = 5566;
+pub const HOST_CURSOR_STREAM_PORT: u32 = 5567;
 pub const CLIENT_FRAME_STREAM_PORT: u32 = 6565;
 
 #[cfg(target_os = "linux")]
@@ -66,6 +76,11 @@ impl Host {
 pub struct Client {
     pub user_id: String,
 
+    /**
+     * the host we connected to, frames come from there too
+     */
+    pub url: String,
+
     /**
      * used for connecting and
      * sending general data
@@ -101,11 +116,13 @@ impl Client {
             rw_primary,
             r_frame,
             user_id: String::from(""),
+            url: String::from(""),
         }
     }
 
     pub fn connect(&mut self, url: String, socket_id: String) {
         self.user_id = socket_id;
+        self.url = url.clone();
 
         let identity = bincode::serialize(&self.user_id).unwrap();
         self.rw_primary.set_identity(&identity).unwrap();
@@ -146,6 +163,49 @@ impl Client {
             .expect("failed subscribing");
     }
 
+    /**
+     * asks the host to start sending us frames
+     */
+    pub fn subscribe(&mut self, settings: &subscribers::ClientSettings) {
+        self.rw_primary.send("SUBSCRIBE", zmq::SNDMORE).unwrap();
+        self.rw_primary
+            .send(
+                bincode::serialize(&CLIENT_FRAME_STREAM_PORT).unwrap(),
+                zmq::SNDMORE,
+            )
+            .unwrap();
+        self.rw_primary
+            .send(settings.cursor.as_str(), zmq::SNDMORE)
+            .unwrap();
+        //  0 lets the host pick
+        self.rw_primary
+            .send(
+                bincode::serialize(&settings.fps.unwrap_or(0)).unwrap(),
+                zmq::SNDMORE,
+            )
+            .unwrap();
+        self.rw_primary
+            .send(&CodecId::list_to_string(&settings.codecs), zmq::SNDMORE)
+            .unwrap();
+        //  0x0 for full size
+        let (width, height) = settings.size.unwrap_or((0, 0));
+        self.rw_primary
+            .send(bincode::serialize(&width).unwrap(), zmq::SNDMORE)
+            .unwrap();
+        self.rw_primary
+            .send(bincode::serialize(&height).unwrap(), zmq::SNDMORE)
+            .unwrap();
+        self.rw_primary.send(settings.filter.as_str(), 0).unwrap();
+
+        let envelope = self.primary_read_envelope();
+        if envelope != "SUBSCRIBE_OK" {
+            panic!(
+                "[C] [{}] host didn't accept our subscription: {}",
+                self.user_id, envelope
+            );
+        }
+    }
+
     pub fn primary_read_envelope(&mut self) -> String {
         self.rw_primary
             .recv_string(0)
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use super::fragments::Fragment;
+
+struct Sent {
+    fragments: Vec<Fragment>,
+    sent: Instant,
+}
+
+/**
+ * the fragments the host sent lately, per client, so the ones
+ * a client NACKs can go out again
+ *
+ * past `deadline` a frame is gone, the client will have given up on it
+ * or be about to, and resending would only hold up newer frames
+ */
+pub struct RetransmitBuffer {
+    frames: HashMap<(SocketAddr, u32), Sent>,
+    deadline: Duration,
+}
+
+impl RetransmitBuffer {
+    pub fn new(deadline: Duration) -> Self {
+        Self {
+            frames: HashMap::new(),
+            deadline,
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        address: SocketAddr,
+        frame_id: u32,
+        fragments: Vec<Fragment>,
+        now: Instant,
+    ) {
+        if self.deadline.is_zero() {
+            return;
+        }
+
+        self.frames.insert(
+            (address, frame_id),
+            Sent {
+                fragments,
+                sent: now,
+            },
+        );
+    }
+
+    /**
+     * the data fragments of `frame_id` at `indexes`, all of them if there
+     * are no indexes, nothing if it's too late
+     */
+    pub fn get(
+        &self,
+        address: SocketAddr,
+        frame_id: u32,
+        indexes: &[u16],
+        now: Instant,
+    ) -> Vec<&Fragment> {
+        let sent = match self.frames.get(&(address, frame_id)) {
+            Some(sent) if now.duration_since(sent.sent) < self.deadline => sent,
+            _ => return vec![],
+        };
+
+        sent.fragments
+            .iter()
+            .filter(|f| !f.parity && (indexes.is_empty() || indexes.contains(&f.index)))
+            .collect()
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        let deadline = self.deadline;
+        self.frames
+            .retain(|_, sent| now.duration_since(sent.sent) < deadline);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::codec::CodecId;
+use crate::recording::scale::ScaleFilter;
+
+/**
+ * how a client wants to see the host's mouse cursor
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CursorMode {
+    Hidden,
+
+    /**
+     * drawn into the frames before they're encoded
+     */
+    Blend,
+
+    /**
+     * published on its own stream, the client draws it
+     */
+    Metadata,
+}
+
+impl CursorMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "hidden" => Some(Self::Hidden),
+            "blend" => Some(Self::Blend),
+            "metadata" => Some(Self::Metadata),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hidden => "hidden",
+            Self::Blend => "blend",
+            Self::Metadata => "metadata",
+        }
+    }
+}
+
+/**
+ * what a client asked for during the handshake
+ */
+#[derive(Clone, Debug)]
+pub struct ClientSettings {
+    pub cursor: CursorMode,
+
+    /**
+     * how many frames a second the client wants,
+     * `None` leaves it up to the host
+     */
+    pub fps: Option<u32>,
+
+    /**
+     * what the client can decode, the host picks one of them
+     */
+    pub codecs: Vec<CodecId>,
+
+    /**
+     * the biggest frames the client wants, the host scales down
+     * to fit keeping the aspect ratio, `None` for whatever gets captured
+     */
+    pub size: Option<(u32, u32)>,
+    pub filter: ScaleFilter,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            cursor: CursorMode::Blend,
+            fps: None,
+            codecs: vec![CodecId::Jpeg],
+            size: None,
+            filter: ScaleFilter::Bilinear,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Subscriber {
+    pub user_id: String,
+
+    /**
+     * where frames for this client go
+     */
+    pub address: SocketAddr,
+    pub settings: ClientSettings,
+
+    /**
+     * what the host settled on out of `settings.codecs`
+     */
+    pub codec: CodecId,
+}
+
+/**
+ * filled in by the handshake, read by whoever sends frames
+ */
+pub type Subscribers = Arc<Mutex<HashMap<String, Subscriber>>>;
@@ -0,0 +1,140 @@
+use std::os::raw::c_int;
+
+use x11::xlib;
+
+const COMPOSITE_REDIRECT_AUTOMATIC: c_int = 0;
+
+extern "C" {
+    fn XCompositeQueryExtension(
+        display: *mut xlib::Display,
+        event_base: *mut c_int,
+        error_base: *mut c_int,
+    ) -> xlib::Bool;
+    fn XCompositeRedirectWindow(display: *mut xlib::Display, window: xlib::Window, update: c_int);
+    fn XCompositeUnredirectWindow(display: *mut xlib::Display, window: xlib::Window, update: c_int);
+    fn XCompositeNameWindowPixmap(
+        display: *mut xlib::Display,
+        window: xlib::Window,
+    ) -> xlib::Pixmap;
+}
+
+pub fn is_supported(display: *mut xlib::Display) -> bool {
+    let mut event_base = 0;
+    let mut error_base = 0;
+
+    unsafe { XCompositeQueryExtension(display, &mut event_base, &mut error_base) != 0 }
+}
+
+/**
+ * redirects a window offscreen so we can read its contents
+ * even while it's covered by other windows
+ *
+ * the redirect is automatic, the server still draws the window
+ * on screen like it normally would
+ *
+ * an unmapped window, like one on another workspace, isn't drawn at all,
+ * the best we can do is hold on to the pixmap named while it was mapped,
+ * so the stream freezes on what it showed last, if it was never
+ * mapped while we watched there is nothing to show
+ */
+pub struct CompositeCapture {
+    display: *mut xlib::Display,
+    pub xid: u64,
+    pixmap: xlib::Pixmap,
+    width: i32,
+    height: i32,
+
+    /**
+     * the window was unmapped since `pixmap` was named,
+     * mapping it again gets it a new one
+     */
+    unmapped: bool,
+}
+
+impl CompositeCapture {
+    pub fn new(display: *mut xlib::Display, xid: u64) -> Self {
+        unsafe {
+            XCompositeRedirectWindow(display, xid, COMPOSITE_REDIRECT_AUTOMATIC);
+        }
+
+        Self {
+            display,
+            xid,
+            pixmap: 0,
+            width: 0,
+            height: 0,
+            unmapped: false,
+        }
+    }
+
+    /**
+     * the pixmap backing the window
+     *
+     * the server hands out a new one whenever the window is resized or remapped,
+     * so the name is refreshed when the size changes, after a remap or after `invalidate`
+     *
+     * while the window is unmapped it's the last one we named, if any
+     */
+    pub fn pixmap(&mut self, attr: &xlib::XWindowAttributes) -> Option<xlib::Pixmap> {
+        if attr.map_state != xlib::IsViewable {
+            self.unmapped = true;
+            //  resized in the meantime, it won't match what we'd read anymore
+            return if self.pixmap != 0 && self.width == attr.width && self.height == attr.height {
+                Some(self.pixmap)
+            } else {
+                None
+            };
+        }
+
+        if self.pixmap != 0
+            && !self.unmapped
+            && self.width == attr.width
+            && self.height == attr.height
+        {
+            return Some(self.pixmap);
+        }
+
+        self.invalidate();
+        self.unmapped = false;
+
+        //  naming fails with BadMatch if the window isn't redirected or viewable yet,
+        //  catch it here instead of having the first grab fail on it
+        let (display, xid) = (self.display, self.xid);
+        let pixmap = match super::trap_errors(display, || unsafe {
+            XCompositeNameWindowPixmap(display, xid)
+        }) {
+            Ok(pixmap) if pixmap != 0 => pixmap,
+            Ok(_) => return None,
+            Err(code) => {
+                println!("[H] can't name the pixmap of {:#x}, X error {}", xid, code);
+                return None;
+            }
+        };
+
+        self.pixmap = pixmap;
+        self.width = attr.width;
+        self.height = attr.height;
+
+        Some(self.pixmap)
+    }
+
+    pub fn invalidate(&mut self) {
+        if self.pixmap != 0 {
+            unsafe {
+                xlib::XFreePixmap(self.display, self.pixmap);
+            }
+        }
+
+        self.pixmap = 0;
+    }
+}
+
+impl Drop for CompositeCapture {
+    fn drop(&mut self) {
+        self.invalidate();
+
+        unsafe {
+            XCompositeUnredirectWindow(self.display, self.xid, COMPOSITE_REDIRECT_AUTOMATIC);
+        }
+    }
+}
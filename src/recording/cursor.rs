@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+
+use super::pixels::{Frame, PixelFormat};
+use super::scale::{ScaleFilter, Scaler};
+
+/**
+ * the mouse cursor as it sits on top of a captured frame
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    /**
+     * where the top-left corner of the cursor image goes,
+     * relative to the frame, can be negative
+     */
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+
+    /**
+     * changes whenever the cursor image does
+     */
+    pub serial: u64,
+
+    /**
+     * premultiplied B, G, R, A, same byte order as `PixelFormat::Bgrx`
+     */
+    pub pixels: Vec<u8>,
+}
+
+impl Cursor {
+    /**
+     * where it goes and how big it is on a frame scaled from `from` to `to`
+     */
+    pub fn scaled(&self, from: (u32, u32), to: (u32, u32)) -> Cursor {
+        if from == to || from.0 == 0 || from.1 == 0 {
+            return self.clone();
+        }
+
+        let (sx, sy) = (to.0 as f64 / from.0 as f64, to.1 as f64 / from.1 as f64);
+        let width = ((self.width as f64 * sx).round() as u32).max(1);
+        let height = ((self.height as f64 * sy).round() as u32).max(1);
+
+        //  premultiplied, so alpha scales just like the colours
+        let image = Frame {
+            data: self.pixels.clone(),
+            width: self.width,
+            height: self.height,
+            format: PixelFormat::Bgrx,
+        };
+        let pixels = Scaler::new(ScaleFilter::Bilinear)
+            .scale(&image, width, height)
+            .data;
+
+        Cursor {
+            x: (self.x as f64 * sx).round() as i32,
+            y: (self.y as f64 * sy).round() as i32,
+            width,
+            height,
+            serial: self.serial,
+            pixels,
+        }
+    }
+}
+
+/**
+ * grabs the cursor image from XFixes and places it relative to `xid`
+ */
+#[cfg(target_os = "linux")]
+pub fn capture(display: *mut x11::xlib::_XDisplay, xid: u64) -> Option<Cursor> {
+    use x11::xlib;
+
+    let image = unsafe { x11::xfixes::XFixesGetCursorImage(display) };
+    if image.is_null() {
+        return None;
+    }
+
+    let (origin_x, origin_y) = unsafe {
+        let mut x = 0;
+        let mut y = 0;
+        let mut child = 0;
+        xlib::XTranslateCoordinates(
+            display,
+            xid,
+            xlib::XDefaultRootWindow(display),
+            0,
+            0,
+            &mut x,
+            &mut y,
+            &mut child,
+        );
+        (x, y)
+    };
+
+    let cursor = unsafe {
+        let c = &*image;
+        let count = c.width as usize * c.height as usize;
+
+        //  each pixel is an ARGB value stored in a whole long
+        let argb = std::slice::from_raw_parts(c.pixels, count);
+        let mut pixels = Vec::with_capacity(count * 4);
+        for p in argb {
+            let p = *p as u32;
+            pixels.extend_from_slice(&[p as u8, (p >> 8) as u8, (p >> 16) as u8, (p >> 24) as u8]);
+        }
+
+        Cursor {
+            x: c.x as i32 - c.xhot as i32 - origin_x,
+            y: c.y as i32 - c.yhot as i32 - origin_y,
+            width: c.width as u32,
+            height: c.height as u32,
+            serial: c.cursor_serial as u64,
+            pixels,
+        }
+    };
+
+    unsafe {
+        xlib::XFree(image as *mut _);
+    }
+
+    Some(cursor)
+}
+
+/**
+ * draws the cursor over a packed frame, clipping whatever falls outside of it
+ */
+pub fn blend(frame: &mut Frame, cursor: &Cursor) {
+    assert!(
+        frame.format.is_packed(),
+        "can't draw a cursor on {:?}",
+        frame.format
+    );
+    let (width, height) = (frame.width, frame.height);
+    let data = &mut frame.data;
+
+    //  the cursor is B, G, R, A, which channel of the frame each of those lands in
+    let channels = match frame.format {
+        PixelFormat::Rgba => [2, 1, 0],
+        _ => [0, 1, 2],
+    };
+
+    for cy in 0..cursor.height as i32 {
+        let y = cursor.y + cy;
+        if y < 0 || y >= height as i32 {
+            continue;
+        }
+
+        for cx in 0..cursor.width as i32 {
+            let x = cursor.x + cx;
+            if x < 0 || x >= width as i32 {
+                continue;
+            }
+
+            let src = ((cy as u32 * cursor.width + cx as u32) * 4) as usize;
+            let dst = ((y as u32 * width + x as u32) * 4) as usize;
+
+            let alpha = cursor.pixels[src + 3] as u32;
+            for (i, &channel) in channels.iter().enumerate() {
+                let under = data[dst + channel] as u32 * (255 - alpha) / 255;
+                data[dst + channel] = (cursor.pixels[src + i] as u32 + under).min(255) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_follows_a_scaled_frame() {
+        let cursor = Cursor {
+            x: 100,
+            y: -8,
+            width: 16,
+            height: 16,
+            serial: 1,
+            pixels: vec![255; 16 * 16 * 4],
+        };
+
+        let scaled = cursor.scaled((1920, 1080), (960, 540));
+        assert_eq!(
+            (scaled.x, scaled.y, scaled.width, scaled.height),
+            (50, -4, 8, 8)
+        );
+        assert_eq!(scaled.pixels.len(), 8 * 8 * 4);
+        assert!(scaled.pixels.iter().all(|&p| p == 255));
+
+        assert_eq!(cursor.scaled((640, 480), (640, 480)), cursor);
+    }
+}
@@ -0,0 +1,106 @@
+use std::os::raw::{c_int, c_ulong};
+
+use x11::xlib;
+
+type Damage = c_ulong;
+
+const XDAMAGE_REPORT_NON_EMPTY: c_int = 3;
+const XDAMAGE_NOTIFY: c_int = 0;
+
+/**
+ * the start of Xdamage's `XDamageNotifyEvent`, the rest we don't read
+ */
+#[repr(C)]
+struct XDamageNotifyEvent {
+    kind: c_int,
+    serial: c_ulong,
+    send_event: xlib::Bool,
+    display: *mut xlib::Display,
+    drawable: xlib::Drawable,
+    damage: Damage,
+}
+
+extern "C" {
+    fn XDamageQueryExtension(
+        display: *mut xlib::Display,
+        event_base: *mut c_int,
+        error_base: *mut c_int,
+    ) -> xlib::Bool;
+    fn XDamageCreate(display: *mut xlib::Display, drawable: xlib::Drawable, level: c_int)
+        -> Damage;
+    fn XDamageDestroy(display: *mut xlib::Display, damage: Damage);
+    fn XDamageSubtract(
+        display: *mut xlib::Display,
+        damage: Damage,
+        repair: c_ulong,
+        parts: c_ulong,
+    );
+}
+
+/**
+ * tells us whether a window was drawn to since we last looked
+ *
+ * uses the non-empty report level, so the server sends a single
+ * notify and then stays quiet until we subtract the damage again
+ */
+pub struct DamageTracker {
+    display: *mut xlib::Display,
+    pub xid: u64,
+    damage: Damage,
+    event_base: c_int,
+}
+
+impl DamageTracker {
+    /**
+     * returns `None` if the server doesn't have the DAMAGE extension
+     */
+    pub fn new(display: *mut xlib::Display, xid: u64) -> Option<Self> {
+        let mut event_base = 0;
+        let mut error_base = 0;
+        if unsafe { XDamageQueryExtension(display, &mut event_base, &mut error_base) } == 0 {
+            return None;
+        }
+
+        let damage = unsafe { XDamageCreate(display, xid, XDAMAGE_REPORT_NON_EMPTY) };
+        if damage == 0 {
+            return None;
+        }
+
+        Some(Self {
+            display,
+            xid,
+            damage,
+            event_base,
+        })
+    }
+
+    /**
+     * true if any of `events` reported damage on our window,
+     * in which case the damage is cleared so we get notified again
+     */
+    pub fn take_damage(&mut self, events: &[xlib::XEvent]) -> bool {
+        //  the display is shared, other trackers' notifies come in here too
+        let damaged = events.iter().any(|e| {
+            e.get_type() == self.event_base + XDAMAGE_NOTIFY && {
+                let notify = unsafe { &*(e as *const xlib::XEvent as *const XDamageNotifyEvent) };
+                notify.drawable == self.xid
+            }
+        });
+
+        if damaged {
+            unsafe {
+                XDamageSubtract(self.display, self.damage, 0, 0);
+            }
+        }
+
+        damaged
+    }
+}
+
+impl Drop for DamageTracker {
+    fn drop(&mut self) {
+        unsafe {
+            XDamageDestroy(self.display, self.damage);
+        }
+    }
+}
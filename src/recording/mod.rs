@@ -1,23 +1,119 @@
 use core::ptr::null;
+use std::collections::HashMap;
 
 #[cfg(target_os = "linux")]
-pub struct Image {
-    //pub data: Option<image::ImageBuffer<image::Rgb<u8>, Vec<u8>>>,
-    //pub data: Option<image::DynamicImage>,
-    pub data: Option<Vec<u8>>,
-    pub width: u32,
-    pub height: u32,
-}
+mod composite;
+pub mod cursor;
+#[cfg(target_os = "linux")]
+pub mod damage;
+pub mod pixels;
+pub mod scale;
+#[cfg(target_os = "linux")]
+pub mod screen;
+#[cfg(target_os = "linux")]
+mod shm;
+pub mod source;
+#[cfg(target_os = "linux")]
+pub mod windows;
 
 #[cfg(target_os = "linux")]
 pub fn open_display() -> *mut x11::xlib::_XDisplay {
     let display = unsafe { x11::xlib::XOpenDisplay(null()) };
 
+    //  the default handler exits the process, which is a bit much
+    //  when the game just closed the window we were capturing
+    unsafe {
+        x11::xlib::XSetErrorHandler(Some(on_x_error));
+    }
+
     display
 }
 
+/**
+ * takes every event the server has queued up for us without blocking
+ */
+#[cfg(target_os = "linux")]
+pub fn drain_events(display: *mut x11::xlib::_XDisplay) -> Vec<x11::xlib::XEvent> {
+    let mut events = vec![];
+
+    unsafe {
+        while x11::xlib::XPending(display) > 0 {
+            let mut event: x11::xlib::XEvent = std::mem::zeroed();
+            x11::xlib::XNextEvent(display, &mut event);
+            events.push(event);
+        }
+    }
+
+    events
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" fn on_x_error(
+    _display: *mut x11::xlib::Display,
+    event: *mut x11::xlib::XErrorEvent,
+) -> std::os::raw::c_int {
+    println!(
+        "X error: code {} (request {}.{}) on {:#x}",
+        (*event).error_code,
+        (*event).request_code,
+        (*event).minor_code,
+        (*event).resourceid
+    );
+
+    0
+}
+
 #[cfg(target_os = "linux")]
-pub fn record_linux(display: *mut x11::xlib::_XDisplay, xid: u64) -> Image {
+static TRAPPED_ERROR: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(0);
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" fn trap_x_error(
+    _display: *mut x11::xlib::Display,
+    event: *mut x11::xlib::XErrorEvent,
+) -> std::os::raw::c_int {
+    //  the first one is the one that matters
+    let _ = TRAPPED_ERROR.compare_exchange(
+        0,
+        (*event).error_code,
+        std::sync::atomic::Ordering::Relaxed,
+        std::sync::atomic::Ordering::Relaxed,
+    );
+
+    0
+}
+
+/**
+ * runs `f` with X errors caught instead of printed,
+ * the code of the first one `f` caused if it did
+ *
+ * requests are asynchronous, so this syncs before and after
+ * to pin exactly what went wrong on `f`
+ */
+#[cfg(target_os = "linux")]
+fn trap_errors<T>(display: *mut x11::xlib::_XDisplay, f: impl FnOnce() -> T) -> Result<T, u8> {
+    use std::sync::atomic::Ordering;
+
+    unsafe {
+        x11::xlib::XSync(display, 0);
+        TRAPPED_ERROR.store(0, Ordering::Relaxed);
+        let previous = x11::xlib::XSetErrorHandler(Some(trap_x_error));
+
+        let result = f();
+
+        x11::xlib::XSync(display, 0);
+        x11::xlib::XSetErrorHandler(previous);
+        match TRAPPED_ERROR.swap(0, Ordering::Relaxed) {
+            0 => Ok(result),
+            code => Err(code),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn window_attributes(
+    display: *mut x11::xlib::_XDisplay,
+    xid: u64,
+) -> Option<x11::xlib::XWindowAttributes> {
     let mut attr: x11::xlib::XWindowAttributes = x11::xlib::XWindowAttributes {
         x: 0,
         y: 0,
@@ -44,100 +140,177 @@ pub fn record_linux(display: *mut x11::xlib::_XDisplay, xid: u64) -> Image {
         screen: null::<x11::xlib::Screen>() as *mut x11::xlib::Screen,
     };
 
-    unsafe {
-        x11::xlib::XGetWindowAttributes(display, xid, core::ptr::addr_of_mut!(attr));
+    let status =
+        unsafe { x11::xlib::XGetWindowAttributes(display, xid, core::ptr::addr_of_mut!(attr)) };
+    if status == 0 {
+        return None;
     }
 
-    let width = attr.width;
-    let height = attr.height;
-    let image = unsafe {
-        x11::xlib::XGetImage(
+    Some(attr)
+}
+
+/**
+ * keeps per-window capture state around between frames
+ *
+ * uses MIT-SHM when the server supports it,
+ * otherwise every frame goes through XGetImage
+ */
+#[cfg(target_os = "linux")]
+pub struct Recorder {
+    display: *mut x11::xlib::_XDisplay,
+    shm_supported: bool,
+    captures: HashMap<u64, shm::ShmCapture>,
+
+    /**
+     * read from the window's composite pixmap instead of the screen,
+     * so covered windows still come out right
+     */
+    composite: bool,
+    composited: HashMap<u64, composite::CompositeCapture>,
+}
+
+#[cfg(target_os = "linux")]
+impl Recorder {
+    pub fn new(display: *mut x11::xlib::_XDisplay, composite: bool) -> Self {
+        let shm_supported = shm::is_supported(display);
+        if !shm_supported {
+            println!("MIT-SHM not available, falling back to XGetImage");
+        }
+
+        let composite_supported = composite::is_supported(display);
+        if composite && !composite_supported {
+            println!("XComposite not available, capturing straight from the screen");
+        }
+
+        Self {
             display,
-            xid,
-            0,
-            0,
-            width as u32,
-            height as u32,
-            0xffffffff,
-            x11::xlib::ZPixmap,
-        )
-    };
-    let slice = unsafe {
-        std::slice::from_raw_parts((*image).data as *const u8, (width * height * 4) as usize)
-    };
+            shm_supported,
+            captures: HashMap::new(),
+            composite: composite && composite_supported,
+            composited: HashMap::new(),
+        }
+    }
 
-    let mut img: Image = Image {
-        data: None,
-        width: attr.width as u32,
-        height: attr.height as u32,
-    };
+    /**
+     * grabs the whole window, or only the part of it inside `area`
+     *
+     * areas are always read straight from the window, never through XComposite
+     */
+    pub fn grab(&mut self, xid: u64, area: Option<screen::Rect>) -> Option<pixels::Frame> {
+        //  only one window is captured at a time,
+        //  let go of whatever we held on to for the previous one
+        self.captures.retain(|k, _| *k == xid);
+        self.composited.retain(|k, _| *k == xid);
 
-    /*
-    let mut _zzz = image::ImageBuffer::from_fn(img.width, img.height, |x, y| unsafe {
-        if let Some(get_pixel) = (*image).funcs.get_pixel {
-            let p = get_pixel(image, x as i32, y as i32);
-            let s = bincode::serialize(&p).unwrap();
-
-            /*
-            println!("p: {:?}", bincode::serialize(&p).unwrap());
-            println!("r: {:?}", bincode::serialize(&r).unwrap());
-            println!("g: {:?}", bincode::serialize(&g).unwrap());
-            println!("b: {:?}", bincode::serialize(&b).unwrap());
-            println!("p & r << 2: {:?}", bincode::serialize(&(p & r)).unwrap()[2]);
-            println!("p & g << 1: {:?}", bincode::serialize(&(p & g)).unwrap()[1]);
-            println!("p & b << 0: {:?}", bincode::serialize(&(p & b)).unwrap()[0]);
-            println!(
-                "pixel {}x{} ({}, {}, {})",
-                x,
-                y,
-                ((p & r) >> 3) as u8,
-                ((p & g) >> 2) as u8,
-                ((p & b) >> 1) as u8
-            );
-            */
-
-            return image::Rgb([s[0], s[1], s[2]]);
-        } else {
-            return image::Rgb([0, 0, 0]);
+        let mut attr = window_attributes(self.display, xid)?;
+        let (drawable, x, y) = match area {
+            Some(area) => {
+                let area = area.clip(attr.width as u32, attr.height as u32)?;
+                attr.width = area.width as i32;
+                attr.height = area.height as i32;
+
+                (xid, area.x, area.y)
+            }
+            None => {
+                let (drawable, offset) = self.drawable(xid, &attr);
+                (drawable, offset, offset)
+            }
+        };
+
+        if let Some(frame) = self.grab_shm(xid, drawable, x, y, &attr) {
+            return Some(frame);
         }
-    });
 
-    */
-    //let mut comp = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_EXT_BGRA);
-    let mut comp = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_EXT_RGBA);
+        get_image(
+            self.display,
+            drawable,
+            x,
+            y,
+            attr.width as u32,
+            attr.height as u32,
+        )
+    }
 
-    comp.set_size(img.width as usize, img.height as usize);
-    comp.set_mem_dest();
-    comp.start_compress();
+    /**
+     * what to read the window contents from, along with the offset
+     * of the window's inside within it
+     */
+    fn drawable(&mut self, xid: u64, attr: &x11::xlib::XWindowAttributes) -> (u64, i32) {
+        if !self.composite {
+            return (xid, 0);
+        }
 
-    // replace with your image data
-    let pixels = slice.to_vec();
-    /*
-    println!(
-        "pixels: {}, first [{:?} ..]",
-        slice.len(),
-        pixels[0..=8].to_vec(),
-    );
-    */
+        let display = self.display;
+        let capture = self
+            .composited
+            .entry(xid)
+            .or_insert_with(|| composite::CompositeCapture::new(display, xid));
 
-    assert!(comp.write_scanlines(&pixels[..]));
+        //  the named pixmap includes the window border
+        match capture.pixmap(attr) {
+            Some(pixmap) => (pixmap, attr.border_width),
+            None => (xid, 0),
+        }
+    }
 
-    comp.finish_compress();
-    let jpeg_bytes = comp.data_to_vec().unwrap();
-    // write to file, etc.
+    fn grab_shm(
+        &mut self,
+        xid: u64,
+        drawable: u64,
+        x: i32,
+        y: i32,
+        attr: &x11::xlib::XWindowAttributes,
+    ) -> Option<pixels::Frame> {
+        if !self.shm_supported {
+            return None;
+        }
 
-    /*
-    println!("pre {}x{}", _zzz.width(), _zzz.height());
-    _zzz = image::imageops::resize(
-        &_zzz,
-        _zzz.width() / 2,
-        _zzz.height() / 2,
-        image::imageops::FilterType::Lanczos3,
-    );
-    println!("post {}x{}", _zzz.width(), _zzz.height());
-    */
+        if !self.captures.contains_key(&xid) {
+            self.captures
+                .insert(xid, shm::ShmCapture::new(self.display)?);
+        }
+
+        let capture = self.captures.get_mut(&xid).unwrap();
+        match capture.grab(drawable, x, y, attr) {
+            Some(image) => Some(pixels::from_ximage(image)),
+            None => {
+                //  window went away or got resized to nothing, start over next frame
+                self.captures.remove(&xid);
+                if let Some(composited) = self.composited.get_mut(&xid) {
+                    composited.invalidate();
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn get_image(
+    display: *mut x11::xlib::_XDisplay,
+    drawable: u64,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+) -> Option<pixels::Frame> {
+    let image = unsafe {
+        x11::xlib::XGetImage(
+            display,
+            drawable,
+            x,
+            y,
+            width,
+            height,
+            0xffffffff,
+            x11::xlib::ZPixmap,
+        )
+    };
+    if image.is_null() {
+        return None;
+    }
 
-    img.data = Some(jpeg_bytes);
+    let frame = unsafe { pixels::from_ximage(&*image) };
 
     unsafe {
         if let Some(destroy_image) = (*image).funcs.destroy_image {
@@ -145,5 +318,5 @@ pub fn record_linux(display: *mut x11::xlib::_XDisplay, xid: u64) -> Image {
         }
     }
 
-    img
+    Some(frame)
 }
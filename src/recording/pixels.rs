@@ -0,0 +1,649 @@
+use std::borrow::Cow;
+
+/**
+ * how the bytes of a `Frame` are laid out
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /**
+     * B, G, R and a byte nobody looks at, what a 24/32-bit
+     * little-endian X server hands us already
+     */
+    Bgrx,
+
+    /**
+     * R, G, B, A, what GL textures, PNG and QOI want
+     */
+    Rgba,
+
+    /**
+     * planar Y, then U, then V, chroma at half the size rounded up
+     * in each direction, BT.601 limited range like the video codecs use
+     */
+    I420,
+}
+
+/**
+ * ids for when the format goes out on the wire along with the pixels,
+ * only the raw zstd codec needs that
+ */
+#[cfg_attr(not(feature = "zstd"), allow(dead_code))]
+impl PixelFormat {
+    pub fn from_u8(format: u8) -> Option<Self> {
+        match format {
+            1 => Some(Self::Bgrx),
+            2 => Some(Self::Rgba),
+            3 => Some(Self::I420),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Self::Bgrx => 1,
+            Self::Rgba => 2,
+            Self::I420 => 3,
+        }
+    }
+}
+
+impl PixelFormat {
+    /**
+     * one pixel in 4 bytes, rows one after the other
+     */
+    pub fn is_packed(&self) -> bool {
+        *self != Self::I420
+    }
+
+    /**
+     * bytes a `width` x `height` frame takes up
+     */
+    pub fn size(&self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        match self {
+            Self::Bgrx | Self::Rgba => width * height * Frame::BYTES_PER_PIXEL,
+            Self::I420 => width * height + 2 * ((width + 1) / 2) * ((height + 1) / 2),
+        }
+    }
+
+    /**
+     * where red, green and blue sit in a packed pixel
+     */
+    fn rgb_offsets(&self) -> (usize, usize, usize) {
+        match self {
+            Self::Bgrx => (2, 1, 0),
+            Self::Rgba => (0, 1, 2),
+            Self::I420 => panic!("I420 isn't packed"),
+        }
+    }
+}
+
+/**
+ * a picture as the rest of the pipeline sees it,
+ * rows are packed tightly whatever the format
+ */
+#[derive(Clone)]
+pub struct Frame {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+}
+
+impl Frame {
+    /**
+     * for the packed formats
+     */
+    pub const BYTES_PER_PIXEL: usize = 4;
+
+    /**
+     * bytes per row of a packed frame
+     */
+    pub fn stride(&self) -> usize {
+        self.width as usize * Self::BYTES_PER_PIXEL
+    }
+
+    pub fn blank(width: u32, height: u32, format: PixelFormat) -> Self {
+        let mut data = vec![0; format.size(width, height)];
+        if format == PixelFormat::I420 {
+            //  black is 16 for luma and 128 for chroma
+            let luma = width as usize * height as usize;
+            data[..luma].iter_mut().for_each(|y| *y = 16);
+            data[luma..].iter_mut().for_each(|c| *c = 128);
+        }
+
+        Self {
+            data,
+            width,
+            height,
+            format,
+        }
+    }
+
+    /**
+     * this frame in `format`, only copied if it has to be converted
+     */
+    pub fn to(&self, format: PixelFormat) -> Cow<'_, Frame> {
+        if self.format == format {
+            Cow::Borrowed(self)
+        } else {
+            Cow::Owned(convert(self, format))
+        }
+    }
+
+    pub fn into_format(self, format: PixelFormat) -> Frame {
+        if self.format == format {
+            self
+        } else {
+            convert(&self, format)
+        }
+    }
+
+    /**
+     * this frame, or a `PixelFormat::Bgrx` copy of it if it isn't packed
+     */
+    pub fn packed(&self) -> Cow<'_, Frame> {
+        if self.format.is_packed() {
+            Cow::Borrowed(self)
+        } else {
+            Cow::Owned(convert(self, PixelFormat::Bgrx))
+        }
+    }
+
+    /**
+     * a copy of the `width` x `height` area at `x`, `y`, clipped to the frame
+     *
+     * packed formats only
+     */
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Frame {
+        assert!(self.format.is_packed(), "can't crop {:?}", self.format);
+        if x >= self.width || y >= self.height {
+            return Frame::blank(0, 0, self.format);
+        }
+        let width = width.min(self.width.saturating_sub(x));
+        let height = height.min(self.height.saturating_sub(y));
+        let row = width as usize * Self::BYTES_PER_PIXEL;
+
+        let mut data = Vec::with_capacity(row * height as usize);
+        for line in y..y + height {
+            let start = line as usize * self.stride() + x as usize * Self::BYTES_PER_PIXEL;
+            data.extend_from_slice(&self.data[start..start + row]);
+        }
+
+        Frame {
+            data,
+            width,
+            height,
+            format: self.format,
+        }
+    }
+
+    /**
+     * draws `other` over this frame with its top-left corner at `x`, `y`,
+     * whatever sticks out is left off
+     *
+     * this one has to be packed, `other` is converted to match it
+     */
+    pub fn blit(&mut self, x: u32, y: u32, other: &Frame) {
+        assert!(self.format.is_packed(), "can't blit onto {:?}", self.format);
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let other = other.to(self.format);
+        let width = other.width.min(self.width.saturating_sub(x));
+        let height = other.height.min(self.height.saturating_sub(y));
+        let row = width as usize * Self::BYTES_PER_PIXEL;
+        let stride = self.stride();
+
+        for line in 0..height as usize {
+            let dst = (y as usize + line) * stride + x as usize * Self::BYTES_PER_PIXEL;
+            let src = line * other.stride();
+            self.data[dst..dst + row].copy_from_slice(&other.data[src..src + row]);
+        }
+    }
+}
+
+fn convert(frame: &Frame, format: PixelFormat) -> Frame {
+    let data = match (frame.format, format) {
+        (PixelFormat::I420, to) => i420_to_packed(frame, to),
+        (from, PixelFormat::I420) => packed_to_i420(frame, from),
+        (from, to) => {
+            let (from_r, _, from_b) = from.rgb_offsets();
+            let (to_r, _, to_b) = to.rgb_offsets();
+
+            let mut data = frame.data.clone();
+            for pixel in data.chunks_exact_mut(Frame::BYTES_PER_PIXEL) {
+                let (r, b) = (pixel[from_r], pixel[from_b]);
+                pixel[to_r] = r;
+                pixel[to_b] = b;
+
+                //  X's fourth byte can be anything, it's opaque as far as RGBA goes
+                if from == PixelFormat::Bgrx {
+                    pixel[3] = 0xff;
+                }
+            }
+            data
+        }
+    };
+
+    Frame {
+        data,
+        width: frame.width,
+        height: frame.height,
+        format,
+    }
+}
+
+/**
+ * each chroma sample is the average of the 2x2 block it covers
+ */
+fn packed_to_i420(frame: &Frame, from: PixelFormat) -> Vec<u8> {
+    let (r_at, g_at, b_at) = from.rgb_offsets();
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+    let stride = frame.stride();
+
+    let mut data = Vec::with_capacity(PixelFormat::I420.size(frame.width, frame.height));
+    for pixel in frame.data.chunks_exact(Frame::BYTES_PER_PIXEL) {
+        let (r, g, b) = (pixel[r_at] as i32, pixel[g_at] as i32, pixel[b_at] as i32);
+        data.push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+    }
+
+    let mut u = Vec::with_capacity(chroma_width * chroma_height);
+    let mut v = Vec::with_capacity(chroma_width * chroma_height);
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let (mut r, mut g, mut b, mut n) = (0, 0, 0, 0);
+            for y in cy * 2..(cy * 2 + 2).min(height) {
+                for x in cx * 2..(cx * 2 + 2).min(width) {
+                    let p = y * stride + x * Frame::BYTES_PER_PIXEL;
+                    r += frame.data[p + r_at] as i32;
+                    g += frame.data[p + g_at] as i32;
+                    b += frame.data[p + b_at] as i32;
+                    n += 1;
+                }
+            }
+            let (r, g, b) = (r / n, g / n, b / n);
+
+            u.push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+            v.push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+        }
+    }
+
+    data.extend_from_slice(&u);
+    data.extend_from_slice(&v);
+    data
+}
+
+fn i420_to_packed(frame: &Frame, to: PixelFormat) -> Vec<u8> {
+    let (r_at, g_at, b_at) = to.rgb_offsets();
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let chroma_width = (width + 1) / 2;
+    let chroma_size = chroma_width * ((height + 1) / 2);
+    let (y, chroma) = frame.data.split_at(width * height);
+    let (u, v) = chroma.split_at(chroma_size);
+
+    let clamp = |value: i32| (value >> 8).max(0).min(255) as u8;
+    let mut data = vec![0xff; width * height * Frame::BYTES_PER_PIXEL];
+    for (i, pixel) in data.chunks_exact_mut(Frame::BYTES_PER_PIXEL).enumerate() {
+        let (row, x) = (i / width, i % width);
+        let c = y[i] as i32 - 16;
+        let d = u[row / 2 * chroma_width + x / 2] as i32 - 128;
+        let e = v[row / 2 * chroma_width + x / 2] as i32 - 128;
+
+        pixel[r_at] = clamp(298 * c + 409 * e + 128);
+        pixel[g_at] = clamp(298 * c - 100 * d - 208 * e + 128);
+        pixel[b_at] = clamp(298 * c + 516 * d + 128);
+    }
+
+    data
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ByteOrder {
+    LsbFirst,
+    MsbFirst,
+}
+
+/**
+ * how the pixels of an XImage are actually laid out in memory
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct PixelLayout {
+    pub width: u32,
+    pub height: u32,
+    pub bytes_per_line: usize,
+    pub bits_per_pixel: u32,
+    pub byte_order: ByteOrder,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+}
+
+impl PixelLayout {
+    #[cfg(target_os = "linux")]
+    pub fn from_ximage(image: &x11::xlib::XImage) -> Self {
+        Self {
+            width: image.width as u32,
+            height: image.height as u32,
+            bytes_per_line: image.bytes_per_line as usize,
+            bits_per_pixel: image.bits_per_pixel as u32,
+            byte_order: if image.byte_order == x11::xlib::MSBFirst {
+                ByteOrder::MsbFirst
+            } else {
+                ByteOrder::LsbFirst
+            },
+            red_mask: image.red_mask as u32,
+            green_mask: image.green_mask as u32,
+            blue_mask: image.blue_mask as u32,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.bytes_per_line * self.height as usize
+    }
+
+    /**
+     * true if rows can be copied straight into a `Frame`
+     */
+    fn is_native(&self) -> bool {
+        self.bits_per_pixel == 32
+            && self.byte_order == ByteOrder::LsbFirst
+            && self.red_mask == 0x00ff_0000
+            && self.green_mask == 0x0000_ff00
+            && self.blue_mask == 0x0000_00ff
+    }
+
+    fn read_pixel(&self, row: &[u8], x: usize) -> u32 {
+        let bytes = (self.bits_per_pixel as usize + 7) / 8;
+        let start = x * bytes;
+
+        let mut pixel = 0u32;
+        for i in 0..bytes {
+            let byte = match self.byte_order {
+                ByteOrder::LsbFirst => row[start + bytes - 1 - i],
+                ByteOrder::MsbFirst => row[start + i],
+            };
+            pixel = (pixel << 8) | byte as u32;
+        }
+
+        pixel
+    }
+}
+
+/**
+ * widens the bits selected by `mask` to a full 0-255 channel
+ */
+fn channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift = mask.trailing_zeros();
+    let max = mask >> shift;
+    let value = (pixel & mask) >> shift;
+
+    ((value as u64 * 255 + max as u64 / 2) / max as u64) as u8
+}
+
+/**
+ * converts whatever the X server gave us into a `PixelFormat::Bgrx` frame,
+ * the unused byte is set to 0xff
+ *
+ * `data` must hold at least `layout.size()` bytes
+ */
+pub fn extract(layout: &PixelLayout, data: &[u8]) -> Frame {
+    let width = layout.width as usize;
+    let height = layout.height as usize;
+    assert!(data.len() >= layout.size());
+
+    let mut frame = Frame {
+        data: vec![0; width * height * Frame::BYTES_PER_PIXEL],
+        width: layout.width,
+        height: layout.height,
+        format: PixelFormat::Bgrx,
+    };
+    let stride = frame.stride();
+
+    for y in 0..height {
+        let row = &data[y * layout.bytes_per_line..(y + 1) * layout.bytes_per_line];
+        let out = &mut frame.data[y * stride..(y + 1) * stride];
+
+        if layout.is_native() {
+            out.copy_from_slice(&row[..stride]);
+            for x in 0..width {
+                out[x * 4 + 3] = 0xff;
+            }
+            continue;
+        }
+
+        for x in 0..width {
+            let pixel = layout.read_pixel(row, x);
+            out[x * 4] = channel(pixel, layout.blue_mask);
+            out[x * 4 + 1] = channel(pixel, layout.green_mask);
+            out[x * 4 + 2] = channel(pixel, layout.red_mask);
+            out[x * 4 + 3] = 0xff;
+        }
+    }
+
+    frame
+}
+
+#[cfg(target_os = "linux")]
+pub fn from_ximage(image: &x11::xlib::XImage) -> Frame {
+    let layout = PixelLayout::from_ximage(image);
+    let data = unsafe { std::slice::from_raw_parts(image.data as *const u8, layout.size()) };
+
+    extract(&layout, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(width: u32, height: u32, bytes_per_line: usize, bits_per_pixel: u32) -> PixelLayout {
+        PixelLayout {
+            width,
+            height,
+            bytes_per_line,
+            bits_per_pixel,
+            byte_order: ByteOrder::LsbFirst,
+            red_mask: 0x00ff_0000,
+            green_mask: 0x0000_ff00,
+            blue_mask: 0x0000_00ff,
+        }
+    }
+
+    fn solid(format: PixelFormat, width: u32, height: u32, pixel: [u8; 4]) -> Frame {
+        Frame {
+            data: pixel.repeat((width * height) as usize),
+            width,
+            height,
+            format,
+        }
+    }
+
+    /**
+     * every pixel is its own x, y
+     */
+    fn numbered(width: u32, height: u32) -> Frame {
+        let mut frame = Frame::blank(width, height, PixelFormat::Bgrx);
+        for (i, p) in frame.data.chunks_exact_mut(4).enumerate() {
+            p.copy_from_slice(&[(i as u32 % width) as u8, (i as u32 / width) as u8, 0, 0]);
+        }
+        frame
+    }
+
+    #[test]
+    fn crop_clips_to_the_frame() {
+        let frame = numbered(8, 6);
+
+        let inside = frame.crop(2, 1, 3, 2);
+        assert_eq!((inside.width, inside.height), (3, 2));
+        assert_eq!(&inside.data[..4], &[2, 1, 0, 0]);
+        assert_eq!(&inside.data[20..], &[4, 2, 0, 0]);
+
+        let edge = frame.crop(6, 4, 10, 10);
+        assert_eq!((edge.width, edge.height), (2, 2));
+        assert_eq!(&edge.data[12..], &[7, 5, 0, 0]);
+
+        let outside = frame.crop(8, 2, 4, 4);
+        assert_eq!(
+            (outside.width, outside.height, outside.data.len()),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn blit_clips_to_the_frame() {
+        let mut frame = Frame::blank(8, 6, PixelFormat::Bgrx);
+        let patch = solid(PixelFormat::Bgrx, 4, 4, [9, 9, 9, 9]);
+
+        frame.blit(6, 4, &patch);
+        let at = |frame: &Frame, x: usize, y: usize| frame.data[(y * 8 + x) * 4];
+        assert_eq!(
+            (at(&frame, 5, 4), at(&frame, 6, 4), at(&frame, 7, 5)),
+            (0, 9, 9)
+        );
+        assert_eq!(frame.data.iter().filter(|&&b| b == 9).count(), 4 * 4);
+
+        //  nothing lands, nothing breaks
+        let before = frame.data.clone();
+        frame.blit(8, 0, &patch);
+        frame.blit(0, 6, &patch);
+        frame.blit(100, 100, &patch);
+        assert_eq!(frame.data, before);
+    }
+
+    #[test]
+    fn bgrx_to_rgba_and_back() {
+        let bgrx = solid(PixelFormat::Bgrx, 1, 1, [1, 2, 3, 0]);
+        let rgba = bgrx.to(PixelFormat::Rgba);
+        assert_eq!(rgba.data, vec![3, 2, 1, 0xff]);
+
+        let back = solid(PixelFormat::Rgba, 1, 1, [3, 2, 1, 7]).into_format(PixelFormat::Bgrx);
+        assert_eq!(back.data, vec![1, 2, 3, 7]);
+    }
+
+    #[test]
+    fn i420_golden_values() {
+        //  B, G, R, X and the Y, U, V BT.601 limited range gives for it
+        let golden = [
+            ([0, 0, 0, 0], [16, 128, 128]),
+            ([255, 255, 255, 0], [235, 128, 128]),
+            ([0, 0, 255, 0], [82, 90, 240]),
+            ([0, 255, 0, 0], [144, 54, 34]),
+            ([255, 0, 0, 0], [41, 240, 110]),
+        ];
+
+        for (bgrx, yuv) in golden.iter() {
+            let frame = solid(PixelFormat::Bgrx, 2, 2, *bgrx).into_format(PixelFormat::I420);
+            assert_eq!(
+                frame.data,
+                vec![yuv[0], yuv[0], yuv[0], yuv[0], yuv[1], yuv[2]]
+            );
+
+            //  and back to within rounding
+            let back = frame.into_format(PixelFormat::Bgrx);
+            for (got, want) in back.data.chunks_exact(4).next().unwrap()[..3]
+                .iter()
+                .zip(&bgrx[..3])
+            {
+                assert!(
+                    (*got as i32 - *want as i32).abs() <= 2,
+                    "{:?} -> {:?}",
+                    bgrx,
+                    back.data
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn i420_with_odd_size() {
+        let frame = solid(PixelFormat::Rgba, 3, 3, [255, 0, 0, 255]).into_format(PixelFormat::I420);
+        assert_eq!(frame.data.len(), 9 + 2 * 4);
+        assert_eq!(frame.into_format(PixelFormat::Rgba).data.len(), 9 * 4);
+    }
+
+    #[test]
+    fn blit_converts_to_the_destination_format() {
+        let mut frame = Frame::blank(2, 1, PixelFormat::Bgrx);
+        frame.blit(1, 0, &solid(PixelFormat::Rgba, 1, 1, [10, 20, 30, 255]));
+
+        assert_eq!(frame.data, vec![0, 0, 0, 0, 30, 20, 10, 255]);
+    }
+
+    #[test]
+    fn native_32bpp_is_copied_with_opaque_alpha() {
+        let data = vec![1, 2, 3, 0, 4, 5, 6, 0];
+        let frame = extract(&layout(2, 1, 8, 32), &data);
+
+        assert_eq!(frame.data, vec![1, 2, 3, 0xff, 4, 5, 6, 0xff]);
+    }
+
+    #[test]
+    fn padded_rows_are_not_sheared() {
+        //  2x2 image, every row padded out to 12 bytes
+        let data = vec![
+            10, 11, 12, 0, 20, 21, 22, 0, 0xee, 0xee, 0xee, 0xee, //
+            30, 31, 32, 0, 40, 41, 42, 0, 0xee, 0xee, 0xee, 0xee,
+        ];
+        let frame = extract(&layout(2, 2, 12, 32), &data);
+
+        assert_eq!(
+            frame.data,
+            vec![10, 11, 12, 0xff, 20, 21, 22, 0xff, 30, 31, 32, 0xff, 40, 41, 42, 0xff]
+        );
+    }
+
+    #[test]
+    fn packed_24bpp() {
+        //  one padding byte at the end of the row
+        let data = vec![1, 2, 3, 4, 5, 6, 0];
+        let frame = extract(&layout(2, 1, 7, 24), &data);
+
+        assert_eq!(frame.data, vec![1, 2, 3, 0xff, 4, 5, 6, 0xff]);
+    }
+
+    #[test]
+    fn rgb565_is_widened() {
+        let mut l = layout(3, 1, 6, 16);
+        l.red_mask = 0xf800;
+        l.green_mask = 0x07e0;
+        l.blue_mask = 0x001f;
+
+        //  pure red, pure green, pure blue in little-endian u16s
+        let data = vec![0x00, 0xf8, 0xe0, 0x07, 0x1f, 0x00];
+        let frame = extract(&l, &data);
+
+        assert_eq!(
+            frame.data,
+            vec![0, 0, 255, 0xff, 0, 255, 0, 0xff, 255, 0, 0, 0xff]
+        );
+    }
+
+    #[test]
+    fn msb_first_32bpp() {
+        let mut l = layout(1, 1, 4, 32);
+        l.byte_order = ByteOrder::MsbFirst;
+
+        //  0x00RRGGBB stored big-endian
+        let data = vec![0x00, 0x30, 0x20, 0x10];
+        let frame = extract(&l, &data);
+
+        assert_eq!(frame.data, vec![0x10, 0x20, 0x30, 0xff]);
+    }
+
+    #[test]
+    fn swapped_channel_masks() {
+        let mut l = layout(1, 1, 4, 32);
+        l.red_mask = 0x0000_00ff;
+        l.blue_mask = 0x00ff_0000;
+
+        let data = vec![0x30, 0x20, 0x10, 0x00];
+        let frame = extract(&l, &data);
+
+        assert_eq!(frame.data, vec![0x10, 0x20, 0x30, 0xff]);
+    }
+}
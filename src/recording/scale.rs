@@ -0,0 +1,359 @@
+use rayon::prelude::*;
+
+use super::pixels::Frame;
+
+/**
+ * how pixels are picked when a frame gets smaller
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ScaleFilter {
+    /**
+     * cheapest, text gets jagged
+     */
+    Nearest,
+    Bilinear,
+
+    /**
+     * 3 lobes, sharpest but the most work
+     */
+    Lanczos,
+}
+
+impl ScaleFilter {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "nearest" => Some(Self::Nearest),
+            "bilinear" => Some(Self::Bilinear),
+            "lanczos" => Some(Self::Lanczos),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Nearest => "nearest",
+            Self::Bilinear => "bilinear",
+            Self::Lanczos => "lanczos",
+        }
+    }
+
+    /**
+     * how far from the sample the filter reaches, in source pixels at 1:1
+     */
+    fn radius(&self) -> f64 {
+        match self {
+            Self::Nearest => 0.0,
+            Self::Bilinear => 1.0,
+            Self::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            Self::Nearest => 0.0,
+            Self::Bilinear => (1.0 - x).max(0.0),
+            Self::Lanczos if x < 1e-8 => 1.0,
+            Self::Lanczos if x < 3.0 => {
+                let pi_x = std::f64::consts::PI * x;
+                3.0 * pi_x.sin() * (pi_x / 3.0).sin() / (pi_x * pi_x)
+            }
+            Self::Lanczos => 0.0,
+        }
+    }
+}
+
+/**
+ * the biggest size with the same aspect ratio as `width` x `height`
+ * that fits in `max_width` x `max_height`, frames are never scaled up
+ *
+ * sizes are kept even since the YUV codecs want that anyway
+ */
+pub fn fit(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+
+    let scale = (max_width as f64 / width as f64).min(max_height as f64 / height as f64);
+    let even = |v: f64| ((v as u32) & !1).max(2);
+    (even(width as f64 * scale), even(height as f64 * scale))
+}
+
+const WEIGHT_BITS: u32 = 14;
+
+/**
+ * which source pixels go into every output pixel along one axis, and how much
+ *
+ * every output pixel reads `len` source pixels from `starts[i]` on,
+ * weights are fixed point with `WEIGHT_BITS` fractional bits
+ */
+struct Taps {
+    starts: Vec<usize>,
+    len: usize,
+    weights: Vec<i32>,
+}
+
+impl Taps {
+    fn new(filter: ScaleFilter, from: u32, to: u32) -> Self {
+        let scale = from as f64 / to as f64;
+
+        if filter == ScaleFilter::Nearest {
+            let starts = (0..to as usize)
+                .map(|i| (((i as f64 + 0.5) * scale) as usize).min(from as usize - 1))
+                .collect();
+            return Self {
+                starts,
+                len: 1,
+                weights: vec![1 << WEIGHT_BITS; to as usize],
+            };
+        }
+
+        //  when shrinking the filter gets stretched over the source,
+        //  otherwise whole rows of source pixels get skipped and we alias
+        let stretch = scale.max(1.0);
+        let support = filter.radius() * stretch;
+        let len = ((support.ceil() as usize) * 2 + 1).min(from as usize);
+
+        let mut starts = Vec::with_capacity(to as usize);
+        let mut weights = Vec::with_capacity(to as usize * len);
+        for i in 0..to as usize {
+            let center = (i as f64 + 0.5) * scale;
+            let start = ((center - support).floor().max(0.0) as usize).min(from as usize - len);
+
+            let mut floats: Vec<f64> = (0..len)
+                .map(|j| filter.weight(((start + j) as f64 + 0.5 - center) / stretch))
+                .collect();
+            let sum: f64 = floats.iter().sum();
+            if sum.abs() < 1e-8 {
+                floats = vec![0.0; len];
+                floats[((center as usize).max(start) - start).min(len - 1)] = 1.0;
+            } else {
+                floats.iter_mut().for_each(|w| *w /= sum);
+            }
+
+            //  rounding can leave the total a little off,
+            //  the biggest weight takes the difference
+            let one = 1 << WEIGHT_BITS;
+            let mut fixed: Vec<i32> = floats
+                .iter()
+                .map(|w| (w * one as f64).round() as i32)
+                .collect();
+            let error = one - fixed.iter().sum::<i32>();
+            let biggest = (0..len).max_by_key(|&j| fixed[j]).unwrap();
+            fixed[biggest] += error;
+
+            starts.push(start);
+            weights.extend_from_slice(&fixed);
+        }
+
+        Self {
+            starts,
+            len,
+            weights,
+        }
+    }
+}
+
+fn clamp(sum: i32) -> u8 {
+    ((sum + (1 << (WEIGHT_BITS - 1))) >> WEIGHT_BITS)
+        .max(0)
+        .min(255) as u8
+}
+
+/**
+ * resizes frames, keeps its tables around since
+ * the sizes hardly ever change from one frame to the next
+ */
+pub struct Scaler {
+    pub filter: ScaleFilter,
+    from: (u32, u32),
+    to: (u32, u32),
+    horizontal: Taps,
+    vertical: Taps,
+
+    /**
+     * the frame scaled vertically but not horizontally yet
+     */
+    buffer: Vec<u8>,
+}
+
+impl Scaler {
+    pub fn new(filter: ScaleFilter) -> Self {
+        Self {
+            filter,
+            from: (0, 0),
+            to: (0, 0),
+            horizontal: Taps::new(filter, 1, 1),
+            vertical: Taps::new(filter, 1, 1),
+            buffer: vec![],
+        }
+    }
+
+    /**
+     * planar frames come back as `PixelFormat::Bgrx`
+     */
+    pub fn scale(&mut self, frame: &Frame, width: u32, height: u32) -> Frame {
+        if frame.width == width && frame.height == height {
+            return frame.clone();
+        }
+        let frame = &*frame.packed();
+        if width == 0 || height == 0 || frame.width == 0 || frame.height == 0 {
+            return Frame::blank(width, height, frame.format);
+        }
+
+        if self.from != (frame.width, frame.height) || self.to != (width, height) {
+            self.from = (frame.width, frame.height);
+            self.to = (width, height);
+            self.horizontal = Taps::new(self.filter, frame.width, width);
+            self.vertical = Taps::new(self.filter, frame.height, height);
+        }
+
+        if self.filter == ScaleFilter::Nearest {
+            return self.pick(frame);
+        }
+
+        //  columns first, that pass vectorizes well and leaves
+        //  the fiddly per-pixel pass with fewer rows to go through,
+        //  output rows don't depend on each other so both go on rayon's pool
+        let bpp = Frame::BYTES_PER_PIXEL;
+        let stride = frame.stride();
+        self.buffer.resize(stride * height as usize, 0);
+
+        let taps = &self.vertical;
+        self.buffer
+            .par_chunks_exact_mut(stride)
+            .zip(taps.weights.par_chunks_exact(taps.len))
+            .zip(taps.starts.par_iter())
+            .for_each_init(
+                || vec![0i32; stride],
+                |sums, ((dst, weights), &start)| {
+                    sums.iter_mut().for_each(|s| *s = 0);
+                    for (j, &w) in weights.iter().enumerate() {
+                        let line = &frame.data[(start + j) * stride..(start + j + 1) * stride];
+                        for (s, &p) in sums.iter_mut().zip(line) {
+                            *s += w * p as i32;
+                        }
+                    }
+                    for (out, &s) in dst.iter_mut().zip(sums.iter()) {
+                        *out = clamp(s);
+                    }
+                },
+            );
+
+        let taps = &self.horizontal;
+        let row = width as usize * bpp;
+        let mut data = vec![0u8; row * height as usize];
+        self.buffer
+            .par_chunks_exact(stride)
+            .zip(data.par_chunks_exact_mut(row))
+            .for_each(|(src, dst)| {
+                for ((out, weights), &start) in dst
+                    .chunks_exact_mut(bpp)
+                    .zip(taps.weights.chunks_exact(taps.len))
+                    .zip(&taps.starts)
+                {
+                    let pixels = &src[start * bpp..(start + taps.len) * bpp];
+
+                    let (mut b, mut g, mut r, mut a) = (0, 0, 0, 0);
+                    for (&w, p) in weights.iter().zip(pixels.chunks_exact(bpp)) {
+                        b += w * p[0] as i32;
+                        g += w * p[1] as i32;
+                        r += w * p[2] as i32;
+                        a += w * p[3] as i32;
+                    }
+                    out.copy_from_slice(&[clamp(b), clamp(g), clamp(r), clamp(a)]);
+                }
+            });
+
+        Frame {
+            data,
+            width,
+            height,
+            format: frame.format,
+        }
+    }
+
+    /**
+     * `ScaleFilter::Nearest`, no arithmetic, just copies
+     */
+    fn pick(&self, frame: &Frame) -> Frame {
+        let bpp = Frame::BYTES_PER_PIXEL;
+        let (width, height) = self.to;
+        let mut data = Vec::with_capacity(width as usize * height as usize * bpp);
+
+        for &y in &self.vertical.starts {
+            let src = &frame.data[y * frame.stride()..(y + 1) * frame.stride()];
+            for &x in &self.horizontal.starts {
+                data.extend_from_slice(&src[x * bpp..(x + 1) * bpp]);
+            }
+        }
+
+        Frame {
+            data,
+            width,
+            height,
+            format: frame.format,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_keeps_the_aspect_ratio() {
+        assert_eq!(fit(1920, 1080, 1280, 1280), (1280, 720));
+        assert_eq!(fit(1080, 1920, 1280, 1280), (720, 1280));
+        assert_eq!(fit(1920, 1080, 1920, 540), (960, 540));
+    }
+
+    #[test]
+    fn fit_never_scales_up() {
+        assert_eq!(fit(640, 480, 1920, 1080), (640, 480));
+        assert_eq!(fit(641, 479, 641, 479), (641, 479));
+    }
+
+    #[test]
+    fn fit_keeps_sizes_even() {
+        let (width, height) = fit(1001, 999, 500, 500);
+        assert_eq!((width % 2, height % 2), (0, 0));
+        assert!(width <= 500 && height <= 500);
+        assert_eq!(fit(4000, 10, 100, 100), (100, 2));
+    }
+
+    #[test]
+    fn taps_add_up_to_one_and_stay_in_range() {
+        let filters = [
+            ScaleFilter::Nearest,
+            ScaleFilter::Bilinear,
+            ScaleFilter::Lanczos,
+        ];
+        let sizes = [
+            (1920, 1280),
+            (1920, 97),
+            (97, 1920),
+            (5, 3),
+            (3, 5),
+            (1, 1),
+            (2, 7),
+        ];
+        for &filter in &filters {
+            for &(from, to) in &sizes {
+                let taps = Taps::new(filter, from, to);
+                assert_eq!(taps.starts.len(), to as usize);
+                assert!(taps.len >= 1 && taps.len <= from as usize);
+                for (weights, &start) in taps.weights.chunks_exact(taps.len).zip(&taps.starts) {
+                    assert_eq!(weights.iter().sum::<i32>(), 1 << WEIGHT_BITS);
+                    assert!(
+                        start + taps.len <= from as usize,
+                        "{:?} {} -> {}",
+                        filter,
+                        from,
+                        to
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::ffi::CStr;
+use std::os::raw::c_int;
+
+use x11::{xlib, xrandr};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /**
+     * the part of the rect that falls inside a `width` x `height` area,
+     * `None` if nothing does
+     */
+    pub fn clip(&self, width: u32, height: u32) -> Option<Rect> {
+        //  in i64, a region far out or a huge size would overflow i32
+        let x = (self.x as i64).max(0);
+        let y = (self.y as i64).max(0);
+        let right = (self.x as i64 + self.width as i64).min(width as i64);
+        let bottom = (self.y as i64 + self.height as i64).min(height as i64);
+
+        if right <= x || bottom <= y {
+            return None;
+        }
+
+        Some(Rect {
+            x: x as i32,
+            y: y as i32,
+            width: (right - x) as u32,
+            height: (bottom - y) as u32,
+        })
+    }
+}
+
+/**
+ * parts of the screen that aren't a single window
+ *
+ * parsed from `root`, `output:<name>` (as `xrandr` prints it)
+ * or `region:<x>,<y>,<width>x<height>`
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScreenTarget {
+    Root,
+    Output(String),
+    Region(Rect),
+}
+
+impl ScreenTarget {
+    pub fn parse(arg: &str) -> Option<Self> {
+        if arg == "root" {
+            return Some(Self::Root);
+        }
+        if let Some(name) = arg.strip_prefix("output:") {
+            return Some(Self::Output(String::from(name)));
+        }
+
+        let region = arg.strip_prefix("region:")?;
+        let (position, size) = region.rsplit_once(',')?;
+        let (x, y) = position.split_once(',')?;
+        let (width, height) = size.split_once('x')?;
+
+        Some(Self::Region(Rect {
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+        }))
+    }
+}
+
+/**
+ * keeps track of where on the root window a `ScreenTarget` is,
+ * following monitors being plugged, moved or resized
+ */
+pub struct ScreenTracker {
+    display: *mut xlib::Display,
+    pub target: ScreenTarget,
+    pub root: u64,
+
+    /**
+     * `None` if XRandR isn't around, we never hear about layout changes then
+     */
+    event_base: Option<c_int>,
+    area: Option<Rect>,
+}
+
+impl ScreenTracker {
+    pub fn new(display: *mut xlib::Display, target: ScreenTarget) -> Self {
+        let root = unsafe { xlib::XDefaultRootWindow(display) };
+
+        let mut event_base = 0;
+        let mut error_base = 0;
+        let event_base =
+            if unsafe { xrandr::XRRQueryExtension(display, &mut event_base, &mut error_base) } != 0
+            {
+                unsafe {
+                    xrandr::XRRSelectInput(
+                        display,
+                        root,
+                        xrandr::RRScreenChangeNotifyMask
+                            | xrandr::RRCrtcChangeNotifyMask
+                            | xrandr::RROutputChangeNotifyMask,
+                    );
+                }
+                Some(event_base)
+            } else {
+                None
+            };
+
+        if event_base.is_none() {
+            if let ScreenTarget::Output(_) = target {
+                println!("XRandR not available, can't look up outputs");
+            }
+        }
+
+        Self {
+            display,
+            target,
+            root,
+            event_base,
+            area: None,
+        }
+    }
+
+    /**
+     * the area of the root window to capture,
+     * looked up again whenever `events` say the layout changed
+     */
+    pub fn area(&mut self, events: &[xlib::XEvent]) -> Option<Rect> {
+        if let Some(event_base) = self.event_base {
+            for event in events {
+                let kind = event.get_type();
+                if kind == event_base + xrandr::RRScreenChangeNotify {
+                    //  lets Xlib know the root window changed size
+                    unsafe {
+                        xrandr::XRRUpdateConfiguration(event as *const _ as *mut _);
+                    }
+                }
+                if kind == event_base + xrandr::RRScreenChangeNotify
+                    || kind == event_base + xrandr::RRNotify
+                {
+                    if self.area.is_some() {
+                        println!("monitor layout changed");
+                    }
+                    self.area = None;
+                }
+            }
+        }
+
+        if self.area.is_none() {
+            self.area = self.lookup();
+        }
+
+        self.area
+    }
+
+    fn lookup(&self) -> Option<Rect> {
+        let root = super::window_attributes(self.display, self.root)?;
+        let (width, height) = (root.width as u32, root.height as u32);
+
+        match &self.target {
+            ScreenTarget::Root => Some(Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }),
+            ScreenTarget::Region(rect) => rect.clip(width, height),
+            ScreenTarget::Output(name) => {
+                self.event_base?;
+
+                let output = list_outputs(self.display)
+                    .into_iter()
+                    .find(|(n, _)| n == name);
+                match output {
+                    Some((_, rect)) => rect.clip(width, height),
+                    None => {
+                        println!("no active output named {}", name);
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+/**
+ * every output that's currently showing something, along with where it sits
+ */
+pub fn list_outputs(display: *mut xlib::Display) -> Vec<(String, Rect)> {
+    let mut outputs = vec![];
+
+    unsafe {
+        let root = xlib::XDefaultRootWindow(display);
+        let resources = xrandr::XRRGetScreenResourcesCurrent(display, root);
+        if resources.is_null() {
+            return outputs;
+        }
+
+        let ids = std::slice::from_raw_parts((*resources).outputs, (*resources).noutput as usize);
+        for id in ids {
+            let output = xrandr::XRRGetOutputInfo(display, resources, *id);
+            if output.is_null() {
+                continue;
+            }
+
+            if (*output).crtc != 0 {
+                let crtc = xrandr::XRRGetCrtcInfo(display, resources, (*output).crtc);
+                if !crtc.is_null() {
+                    outputs.push((
+                        CStr::from_ptr((*output).name)
+                            .to_string_lossy()
+                            .into_owned(),
+                        Rect {
+                            x: (*crtc).x,
+                            y: (*crtc).y,
+                            width: (*crtc).width,
+                            height: (*crtc).height,
+                        },
+                    ));
+                    xrandr::XRRFreeCrtcInfo(crtc);
+                }
+            }
+
+            xrandr::XRRFreeOutputInfo(output);
+        }
+
+        xrandr::XRRFreeScreenResources(resources);
+    }
+
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(ScreenTarget::parse("root"), Some(ScreenTarget::Root));
+        assert_eq!(
+            ScreenTarget::parse("output:DP-1"),
+            Some(ScreenTarget::Output(String::from("DP-1")))
+        );
+        assert_eq!(
+            ScreenTarget::parse("region:-10,20,640x480"),
+            Some(ScreenTarget::Region(rect(-10, 20, 640, 480)))
+        );
+
+        for broken in [
+            "",
+            "screen",
+            "region:10,20",
+            "region:10,20,640",
+            "region:a,20,640x480",
+            "region:10,20,-640x480",
+            "region:10,20,640x480x2",
+        ] {
+            assert_eq!(ScreenTarget::parse(broken), None, "{}", broken);
+        }
+    }
+
+    #[test]
+    fn clips_to_the_area() {
+        assert_eq!(
+            rect(10, 20, 100, 50).clip(1920, 1080),
+            Some(rect(10, 20, 100, 50))
+        );
+        assert_eq!(
+            rect(-10, -20, 100, 50).clip(1920, 1080),
+            Some(rect(0, 0, 90, 30))
+        );
+        assert_eq!(
+            rect(1900, 1000, 100, 100).clip(1920, 1080),
+            Some(rect(1900, 1000, 20, 80))
+        );
+        assert_eq!(rect(1920, 0, 100, 100).clip(1920, 1080), None);
+        assert_eq!(rect(-100, 0, 100, 100).clip(1920, 1080), None);
+    }
+
+    #[test]
+    fn clipping_huge_regions_doesnt_overflow() {
+        assert_eq!(rect(i32::MAX, 0, u32::MAX, 10).clip(1920, 1080), None);
+        assert_eq!(
+            rect(10, 10, u32::MAX, u32::MAX).clip(1920, 1080),
+            Some(rect(10, 10, 1910, 1070))
+        );
+        assert_eq!(
+            rect(i32::MIN, i32::MIN, u32::MAX, u32::MAX).clip(1920, 1080),
+            Some(rect(0, 0, 1920, 1080))
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use core::ptr::null_mut;
+use std::os::raw::{c_char, c_int, c_uint, c_ulong};
+
+use x11::xlib;
+
+/**
+ * mirrors `XShmSegmentInfo` from <X11/extensions/XShm.h>
+ *
+ * the `x11` crate keeps the fields private, so we can't fill it in ourselves
+ */
+#[repr(C)]
+struct SegmentInfo {
+    shmseg: c_ulong,
+    shmid: c_int,
+    shmaddr: *mut c_char,
+    read_only: xlib::Bool,
+}
+
+extern "C" {
+    fn XShmQueryExtension(display: *mut xlib::Display) -> xlib::Bool;
+    fn XShmCreateImage(
+        display: *mut xlib::Display,
+        visual: *mut xlib::Visual,
+        depth: c_uint,
+        format: c_int,
+        data: *mut c_char,
+        shminfo: *mut SegmentInfo,
+        width: c_uint,
+        height: c_uint,
+    ) -> *mut xlib::XImage;
+    fn XShmAttach(display: *mut xlib::Display, shminfo: *mut SegmentInfo) -> xlib::Bool;
+    fn XShmDetach(display: *mut xlib::Display, shminfo: *mut SegmentInfo) -> xlib::Bool;
+    fn XShmGetImage(
+        display: *mut xlib::Display,
+        drawable: xlib::Drawable,
+        image: *mut xlib::XImage,
+        x: c_int,
+        y: c_int,
+        plane_mask: c_ulong,
+    ) -> xlib::Bool;
+}
+
+pub fn is_supported(display: *mut xlib::Display) -> bool {
+    unsafe { XShmQueryExtension(display) != 0 }
+}
+
+/**
+ * one shared memory segment attached to the X server,
+ * reused for every frame grabbed from the same window
+ */
+pub struct ShmCapture {
+    display: *mut xlib::Display,
+
+    /**
+     * boxed so the address we hand to the X server stays put
+     */
+    segment: Box<SegmentInfo>,
+    image: *mut xlib::XImage,
+}
+
+impl ShmCapture {
+    /**
+     * returns `None` if the server doesn't speak MIT-SHM
+     */
+    pub fn new(display: *mut xlib::Display) -> Option<Self> {
+        if !is_supported(display) {
+            return None;
+        }
+
+        Some(Self {
+            display,
+            segment: Box::new(SegmentInfo {
+                shmseg: 0,
+                shmid: -1,
+                shmaddr: null_mut(),
+                read_only: 0,
+            }),
+            image: null_mut(),
+        })
+    }
+
+    /**
+     * grabs a `attr.width` x `attr.height` area of `drawable`,
+     * starting at `x`/`y`, into the shared segment
+     *
+     * the segment is rebuilt whenever the size changes,
+     * the returned image is only valid until the next call
+     */
+    pub fn grab(
+        &mut self,
+        drawable: xlib::Drawable,
+        x: i32,
+        y: i32,
+        attr: &xlib::XWindowAttributes,
+    ) -> Option<&xlib::XImage> {
+        let resized = self.image.is_null()
+            || unsafe { (*self.image).width != attr.width || (*self.image).height != attr.height };
+        if resized {
+            self.detach();
+            if !self.attach(attr) {
+                return None;
+            }
+        }
+
+        let ok =
+            unsafe { XShmGetImage(self.display, drawable, self.image, x, y, xlib::XAllPlanes()) };
+        if ok == 0 {
+            return None;
+        }
+
+        unsafe { Some(&*self.image) }
+    }
+
+    fn attach(&mut self, attr: &xlib::XWindowAttributes) -> bool {
+        if attr.width <= 0 || attr.height <= 0 {
+            return false;
+        }
+
+        unsafe {
+            let image = XShmCreateImage(
+                self.display,
+                attr.visual,
+                attr.depth as c_uint,
+                xlib::ZPixmap,
+                null_mut(),
+                &mut *self.segment,
+                attr.width as c_uint,
+                attr.height as c_uint,
+            );
+            if image.is_null() {
+                return false;
+            }
+
+            let size = ((*image).bytes_per_line * (*image).height) as usize;
+            let shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if shmid < 0 {
+                destroy_image(image);
+                return false;
+            }
+
+            let shmaddr = libc::shmat(shmid, null_mut(), 0);
+            if shmaddr as isize == -1 {
+                libc::shmctl(shmid, libc::IPC_RMID, null_mut());
+                destroy_image(image);
+                return false;
+            }
+
+            self.segment.shmid = shmid;
+            self.segment.shmaddr = shmaddr as *mut c_char;
+            self.segment.read_only = 0;
+            (*image).data = self.segment.shmaddr;
+
+            if XShmAttach(self.display, &mut *self.segment) == 0 {
+                libc::shmdt(shmaddr);
+                libc::shmctl(shmid, libc::IPC_RMID, null_mut());
+                (*image).data = null_mut();
+                destroy_image(image);
+                return false;
+            }
+
+            //  make sure the server attached before we mark the segment for removal,
+            //  it'll stick around until both sides detach
+            xlib::XSync(self.display, 0);
+            libc::shmctl(shmid, libc::IPC_RMID, null_mut());
+
+            self.image = image;
+        }
+
+        true
+    }
+
+    fn detach(&mut self) {
+        if self.image.is_null() {
+            return;
+        }
+
+        unsafe {
+            XShmDetach(self.display, &mut *self.segment);
+            xlib::XSync(self.display, 0);
+            libc::shmdt(self.segment.shmaddr as *const libc::c_void);
+
+            //  the data belongs to the segment, don't let Xlib free() it
+            (*self.image).data = null_mut();
+            destroy_image(self.image);
+        }
+
+        self.segment.shmaddr = null_mut();
+        self.segment.shmid = -1;
+        self.image = null_mut();
+    }
+}
+
+impl Drop for ShmCapture {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+unsafe fn destroy_image(image: *mut xlib::XImage) {
+    if let Some(destroy_image) = (*image).funcs.destroy_image {
+        destroy_image(image);
+    }
+}
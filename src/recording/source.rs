@@ -0,0 +1,654 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::cursor::Cursor;
+use super::pixels::{Frame, PixelFormat};
+use crate::codec::{Decoders, Packet};
+
+/**
+ * anything the host can stream frames from
+ */
+pub trait CaptureSource {
+    /**
+     * true if there's something new to capture since the last frame,
+     * sources that can't tell always say yes
+     */
+    fn changed(&mut self) -> bool {
+        true
+    }
+
+    /**
+     * `None` if there's nothing to capture right now
+     */
+    fn capture(&mut self) -> Option<Frame>;
+
+    /**
+     * the cursor over the last captured frame, if this source has one
+     */
+    fn cursor(&mut self) -> Option<Cursor> {
+        None
+    }
+}
+
+/**
+ * picks the source the host was told to use
+ */
+pub fn open(config: &crate::config::HostConfig) -> Box<dyn CaptureSource> {
+    if let Some(size) = config.source.strip_prefix("synthetic") {
+        let (width, height) = size
+            .strip_prefix(':')
+            .and_then(|s| s.split_once('x'))
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+            .unwrap_or((1280, 720));
+
+        return Box::new(SyntheticSource::new(width, height));
+    }
+
+    if let Some(path) = config.source.strip_prefix("playback:") {
+        let source = PlaybackSource::open(Path::new(path))
+            .expect(&format!("failed opening {} for playback", path));
+        return Box::new(source);
+    }
+
+    open_x11(config)
+}
+
+#[cfg(target_os = "linux")]
+fn open_x11(config: &crate::config::HostConfig) -> Box<dyn CaptureSource> {
+    let target = match super::screen::ScreenTarget::parse(&config.target) {
+        Some(screen) => X11Target::Screen(screen),
+        None => X11Target::Window(super::windows::WindowMatcher::parse(&config.target).expect(
+            &format!(
+                "can't make sense of capture target {}, try title:<text>, class:<name>, pid:<pid>, \
+                 a hex XID, root, output:<name> or region:<x>,<y>,<width>x<height>",
+                config.target
+            ),
+        )),
+    };
+
+    Box::new(X11Source::new(target, config.composite, config.damage))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_x11(_config: &crate::config::HostConfig) -> Box<dyn CaptureSource> {
+    panic!("X11 capture is only available on linux");
+}
+
+#[cfg(target_os = "linux")]
+pub enum X11Target {
+    Window(super::windows::WindowMatcher),
+    Screen(super::screen::ScreenTarget),
+}
+
+#[cfg(target_os = "linux")]
+enum Tracker {
+    Window(super::windows::WindowTarget),
+    Screen(super::screen::ScreenTracker),
+}
+
+/**
+ * the game window or part of the screen, read from the X server
+ */
+#[cfg(target_os = "linux")]
+pub struct X11Source {
+    display: *mut x11::xlib::_XDisplay,
+    recorder: super::Recorder,
+    tracker: Tracker,
+    xid: Option<u64>,
+
+    /**
+     * only set when capturing part of the screen
+     */
+    area: Option<super::screen::Rect>,
+
+    /**
+     * `None` when damage tracking is off or unsupported
+     */
+    damage: Option<super::damage::DamageTracker>,
+    use_damage: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl X11Source {
+    pub fn new(target: X11Target, composite: bool, damage: bool) -> Self {
+        let display = super::open_display();
+
+        let tracker = match target {
+            X11Target::Window(matcher) => {
+                Tracker::Window(super::windows::WindowTarget::new(matcher))
+            }
+            X11Target::Screen(screen) => {
+                Tracker::Screen(super::screen::ScreenTracker::new(display, screen))
+            }
+        };
+
+        Self {
+            display,
+            recorder: super::Recorder::new(display, composite),
+            tracker,
+            xid: None,
+            area: None,
+            damage: None,
+            use_damage: damage,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl CaptureSource for X11Source {
+    fn changed(&mut self) -> bool {
+        let events = super::drain_events(self.display);
+
+        let xid = match &mut self.tracker {
+            Tracker::Window(target) => target.resolve(self.display),
+            Tracker::Screen(tracker) => {
+                self.area = tracker.area(&events);
+                self.area.map(|_| tracker.root)
+            }
+        };
+        let xid = match xid {
+            Some(xid) => xid,
+            None => {
+                println!("nothing to capture yet");
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                self.xid = None;
+                return false;
+            }
+        };
+        self.xid = Some(xid);
+
+        if !self.use_damage {
+            return true;
+        }
+
+        if self.damage.as_ref().map_or(true, |d| d.xid != xid) {
+            self.damage = super::damage::DamageTracker::new(self.display, xid);
+            if self.damage.is_none() {
+                println!("XDamage not available, capturing every frame");
+                self.use_damage = false;
+            }
+        }
+
+        self.damage
+            .as_mut()
+            .map_or(true, |d| d.take_damage(&events))
+    }
+
+    fn capture(&mut self) -> Option<Frame> {
+        self.recorder.grab(self.xid?, self.area)
+    }
+
+    fn cursor(&mut self) -> Option<Cursor> {
+        let mut cursor = super::cursor::capture(self.display, self.xid?)?;
+        if let Some(area) = self.area {
+            cursor.x -= area.x;
+            cursor.y -= area.y;
+        }
+
+        Some(cursor)
+    }
+}
+
+/**
+ * moving colour bars with a frame counter and a timestamp,
+ * for running the host without a display
+ */
+pub struct SyntheticSource {
+    width: u32,
+    height: u32,
+    count: u64,
+    started: Instant,
+}
+
+impl SyntheticSource {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            count: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+/**
+ * B, G, R of the classic SMPTE bars
+ */
+const BARS: [[u8; 3]; 8] = [
+    [0xc0, 0xc0, 0xc0],
+    [0x00, 0xc0, 0xc0],
+    [0xc0, 0xc0, 0x00],
+    [0x00, 0xc0, 0x00],
+    [0xc0, 0x00, 0xc0],
+    [0x00, 0x00, 0xc0],
+    [0xc0, 0x00, 0x00],
+    [0x10, 0x10, 0x10],
+];
+
+/**
+ * 3x5 bitmaps for 0-9, one row per 3 bits
+ */
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+impl SyntheticSource {
+    fn draw_number(&self, frame: &mut Frame, mut x: u32, y: u32, number: u64) {
+        const SCALE: u32 = 4;
+
+        for digit in number.to_string().bytes().map(|b| (b - b'0') as usize) {
+            for (row, bits) in DIGITS[digit].iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) == 0 {
+                        continue;
+                    }
+
+                    for dy in 0..SCALE {
+                        for dx in 0..SCALE {
+                            let px = x + col * SCALE + dx;
+                            let py = y + row as u32 * SCALE + dy;
+                            if px >= frame.width || py >= frame.height {
+                                continue;
+                            }
+
+                            let i = (py * frame.width + px) as usize * Frame::BYTES_PER_PIXEL;
+                            frame.data[i..i + 4].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+                        }
+                    }
+                }
+            }
+
+            x += 4 * SCALE;
+        }
+    }
+}
+
+impl CaptureSource for SyntheticSource {
+    fn capture(&mut self) -> Option<Frame> {
+        let mut frame = Frame {
+            data: vec![0; (self.width * self.height) as usize * Frame::BYTES_PER_PIXEL],
+            width: self.width,
+            height: self.height,
+            format: PixelFormat::Bgrx,
+        };
+
+        let bar_width = (self.width / BARS.len() as u32).max(1);
+        let scroll = (self.count * 4) as u32;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let bar = ((x + scroll) / bar_width) as usize % BARS.len();
+                let i = (y * self.width + x) as usize * Frame::BYTES_PER_PIXEL;
+
+                frame.data[i..i + 3].copy_from_slice(&BARS[bar]);
+                frame.data[i + 3] = 0xff;
+            }
+        }
+
+        self.draw_number(&mut frame, 8, 8, self.count);
+        self.draw_number(&mut frame, 8, 36, self.started.elapsed().as_millis() as u64);
+
+        self.count += 1;
+        Some(frame)
+    }
+}
+
+/**
+ * plays back a directory of PNG/JPEG frames or a recorded session, looping forever
+ *
+ * images go out one per frame at whatever rate the host paces at,
+ * sessions keep the timing they were recorded with, frames that aren't
+ * due yet are held back and ones the host was too slow for are skipped
+ */
+pub struct PlaybackSource {
+    frames: Playback,
+    position: usize,
+    decoders: Decoders,
+
+    /**
+     * when the session got to its first frame, this time around
+     */
+    started: Option<Instant>,
+
+    /**
+     * sessions recorded with `--codec tiles` only have what changed
+     */
+    retained: Option<Frame>,
+}
+
+enum Playback {
+    Images(Vec<PathBuf>),
+
+    /**
+     * ms since recording started, and the packet still encoded, sessions get big
+     */
+    Session(Vec<(u64, Vec<u8>)>),
+}
+
+impl PlaybackSource {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let frames = if path.is_dir() {
+            let mut images: Vec<PathBuf> = std::fs::read_dir(path)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| {
+                    let ext = p.extension().and_then(|e| e.to_str()).unwrap_or("");
+                    ["png", "jpg", "jpeg"].contains(&ext.to_lowercase().as_str())
+                })
+                .collect();
+            images.sort();
+
+            Playback::Images(images)
+        } else {
+            Playback::Session(read_session(path)?)
+        };
+
+        Ok(Self {
+            frames,
+            position: 0,
+            decoders: Decoders::default(),
+            started: None,
+            retained: None,
+        })
+    }
+}
+
+impl CaptureSource for PlaybackSource {
+    fn capture(&mut self) -> Option<Frame> {
+        let frame = match &self.frames {
+            Playback::Images(images) if !images.is_empty() => {
+                let path = &images[self.position % images.len()];
+                let image = match image::open(path) {
+                    Ok(image) => image.to_rgba8(),
+                    Err(e) => {
+                        println!("skipping {}: {}", path.display(), e);
+                        self.position += 1;
+                        return None;
+                    }
+                };
+
+                Frame {
+                    width: image.width(),
+                    height: image.height(),
+                    data: image.into_raw(),
+                    format: PixelFormat::Rgba,
+                }
+            }
+            Playback::Session(frames) if !frames.is_empty() => {
+                if self.position >= frames.len() {
+                    self.position = 0;
+                    self.started = None;
+                }
+                let started = *self.started.get_or_insert_with(Instant::now);
+                let now = frames[0].0 + started.elapsed().as_millis() as u64;
+                if frames[self.position].0 > now {
+                    return None;
+                }
+
+                //  everything that's due goes in, in order
+                let decoders = &mut self.decoders;
+                let retained = &mut self.retained;
+                let mut patched = false;
+                while self.position < frames.len() && frames[self.position].0 <= now {
+                    let packet = Packet::from_bytes(&frames[self.position].1);
+                    let update = packet.and_then(|p| decoders.decode_update(&p));
+                    match update.map(|u| u.apply(retained, PixelFormat::Bgrx)) {
+                        Some(true) => patched = true,
+                        _ => println!("skipping broken frame {}", self.position),
+                    }
+                    self.position += 1;
+                }
+
+                return if patched { retained.clone() } else { None };
+            }
+            _ => return None,
+        };
+
+        self.position += 1;
+        Some(frame)
+    }
+}
+
+const SESSION_MAGIC: &[u8; 8] = b"SNOWSES2";
+
+/**
+ * appends every frame the host streams to a file
+ * that `PlaybackSource` can play back later
+ *
+ * frames are stored as the packets that went out on the wire
+ */
+pub struct SessionWriter {
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl SessionWriter {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(SESSION_MAGIC)?;
+
+        Ok(Self {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, packet: &Packet) -> std::io::Result<()> {
+        let bytes = packet.to_bytes();
+
+        self.file
+            .write_u64::<LittleEndian>(self.started.elapsed().as_millis() as u64)?;
+        self.file.write_u32::<LittleEndian>(bytes.len() as u32)?;
+        self.file.write_all(&bytes)?;
+
+        self.file.flush()
+    }
+}
+
+/**
+ * every frame in the session along with when it was recorded
+ *
+ * a host killed mid-write leaves half a frame at the end,
+ * what came before it still plays
+ */
+fn read_session(path: &Path) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+    let file = File::open(path)?;
+    let mut remaining = file.metadata()?.len();
+    let mut file = BufReader::new(file);
+
+    let mut magic = [0u8; 8];
+    file.read_exact(&mut magic)?;
+    if &magic != SESSION_MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "not a recorded session",
+        ));
+    }
+
+    remaining = remaining.saturating_sub(magic.len() as u64);
+
+    let mut frames = vec![];
+    while let Ok(timestamp) = file.read_u64::<LittleEndian>() {
+        let len = match file.read_u32::<LittleEndian>() {
+            Ok(len) => len,
+            Err(_) => break,
+        };
+        //  a length past the end of the file is the partial tail, or garbage
+        remaining = remaining.saturating_sub(12);
+        if len as u64 > remaining {
+            break;
+        }
+        remaining -= len as u64;
+
+        let mut packet = vec![0; len as usize];
+        if file.read_exact(&mut packet).is_err() {
+            break;
+        }
+        frames.push((timestamp, packet));
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::CodecId;
+
+    /**
+     * a path under the temp dir nobody else is using, removed on drop
+     */
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "snow-{}-{}-{:?}",
+                name,
+                std::process::id(),
+                std::thread::current().id()
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn solid(value: u8) -> Frame {
+        Frame {
+            data: vec![value; 8 * 4 * 4],
+            width: 8,
+            height: 4,
+            format: PixelFormat::Bgrx,
+        }
+    }
+
+    fn qoi(frame: &Frame) -> Packet {
+        crate::codec::encoder(
+            CodecId::Qoi,
+            &crate::config::HostConfig::default().encoder_settings(),
+        )
+        .encode(frame)
+    }
+
+    #[test]
+    fn synthetic_frames_move() {
+        let mut source = SyntheticSource::new(64, 48);
+        let first = source.capture().unwrap();
+        let second = source.capture().unwrap();
+
+        assert_eq!(
+            (first.width, first.height, first.format),
+            (64, 48, PixelFormat::Bgrx)
+        );
+        assert_eq!(first.data.len(), 64 * 48 * 4);
+        assert_ne!(first.data, second.data);
+    }
+
+    #[test]
+    fn images_play_in_order_and_loop() {
+        let dir = Scratch::new("images");
+        std::fs::create_dir(&dir.0).unwrap();
+        for (name, value) in [("b.png", 200u8), ("a.png", 100), ("notes.txt", 0)] {
+            let image = image::RgbaImage::from_pixel(8, 4, image::Rgba([value, value, value, 255]));
+            if name.ends_with(".png") {
+                image.save(dir.0.join(name)).unwrap();
+            } else {
+                std::fs::write(dir.0.join(name), b"not a frame").unwrap();
+            }
+        }
+
+        let mut source = PlaybackSource::open(&dir.0).unwrap();
+        let firsts: Vec<u8> = (0..3).map(|_| source.capture().unwrap().data[0]).collect();
+        assert_eq!(firsts, vec![100, 200, 100]);
+    }
+
+    #[test]
+    fn sessions_read_back_what_was_written() {
+        let path = Scratch::new("session");
+        let packets: Vec<Packet> = [10, 20, 30].iter().map(|&v| qoi(&solid(v))).collect();
+
+        let mut writer = SessionWriter::create(&path.0).unwrap();
+        for packet in &packets {
+            writer.write(packet).unwrap();
+        }
+        drop(writer);
+
+        let frames = read_session(&path.0).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.windows(2).all(|w| w[0].0 <= w[1].0));
+        for ((_, bytes), packet) in frames.iter().zip(&packets) {
+            assert_eq!(Packet::from_bytes(bytes).as_ref(), Some(packet));
+        }
+
+        std::fs::write(&path.0, b"SNOWSES1").unwrap();
+        assert!(read_session(&path.0).is_err());
+    }
+
+    #[test]
+    fn sessions_keep_what_came_before_a_partial_tail() {
+        let path = Scratch::new("partial");
+        let mut writer = SessionWriter::create(&path.0).unwrap();
+        writer.write(&qoi(&solid(10))).unwrap();
+        drop(writer);
+        let whole = std::fs::read(&path.0).unwrap();
+
+        //  cut off mid-frame
+        let mut bytes = whole.clone();
+        bytes.extend_from_slice(&whole[SESSION_MAGIC.len()..whole.len() - 3]);
+        std::fs::write(&path.0, &bytes).unwrap();
+        assert_eq!(read_session(&path.0).unwrap().len(), 1);
+
+        //  a length way past the end isn't allocated
+        let mut bytes = whole;
+        bytes.write_u64::<LittleEndian>(2000).unwrap();
+        bytes.write_u32::<LittleEndian>(u32::MAX).unwrap();
+        std::fs::write(&path.0, &bytes).unwrap();
+        assert_eq!(read_session(&path.0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sessions_play_at_the_recorded_pace() {
+        let path = Scratch::new("paced");
+        let mut bytes = SESSION_MAGIC.to_vec();
+        for (at, value) in [(1000u64, 10u8), (1000, 20), (1100, 30)] {
+            let packet = qoi(&solid(value)).to_bytes();
+            bytes.write_u64::<LittleEndian>(at).unwrap();
+            bytes
+                .write_u32::<LittleEndian>(packet.len() as u32)
+                .unwrap();
+            bytes.extend_from_slice(&packet);
+        }
+        std::fs::write(&path.0, bytes).unwrap();
+
+        let mut source = PlaybackSource::open(&path.0).unwrap();
+
+        //  the first two were recorded together, the later one isn't due yet
+        assert_eq!(source.capture().unwrap().data[0], 20);
+        assert!(source.capture().is_none());
+
+        std::thread::sleep(std::time::Duration::from_millis(120));
+        assert_eq!(source.capture().unwrap().data[0], 30);
+
+        //  and around again
+        assert_eq!(source.capture().unwrap().data[0], 20);
+    }
+}
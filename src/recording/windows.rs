@@ -0,0 +1,272 @@
+use core::ptr::null_mut;
+use std::ffi::CString;
+use std::os::raw::{c_int, c_uchar, c_uint, c_ulong};
+
+use x11::xlib;
+
+pub struct WindowInfo {
+    pub xid: u64,
+    pub title: Option<String>,
+
+    /**
+     * instance and class names from WM_CLASS
+     */
+    pub class: Vec<String>,
+    pub pid: Option<u32>,
+    pub width: u32,
+    pub height: u32,
+    pub viewable: bool,
+}
+
+/**
+ * what the host was asked to capture
+ *
+ * parsed from `title:<text>`, `class:<name>`, `pid:<pid>`
+ * or a bare hex XID like `xwininfo` prints
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum WindowMatcher {
+    Xid(u64),
+    Title(String),
+    Class(String),
+    Pid(u32),
+}
+
+impl WindowMatcher {
+    pub fn parse(arg: &str) -> Option<Self> {
+        if let Some(title) = arg.strip_prefix("title:") {
+            return Some(Self::Title(title.to_lowercase()));
+        }
+        if let Some(class) = arg.strip_prefix("class:") {
+            return Some(Self::Class(class.to_lowercase()));
+        }
+        if let Some(pid) = arg.strip_prefix("pid:") {
+            return pid.parse().ok().map(Self::Pid);
+        }
+
+        let hex = arg.trim_start_matches("0x");
+        u64::from_str_radix(hex, 16).ok().map(Self::Xid)
+    }
+
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        match self {
+            Self::Xid(xid) => window.xid == *xid,
+            Self::Title(title) => window
+                .title
+                .as_ref()
+                .map_or(false, |t| t.to_lowercase().contains(title.as_str())),
+            Self::Class(class) => window.class.iter().any(|c| c.to_lowercase() == *class),
+            Self::Pid(pid) => window.pid == Some(*pid),
+        }
+    }
+}
+
+/**
+ * keeps track of the window we're capturing
+ * and looks it up again if the game recreates it
+ */
+pub struct WindowTarget {
+    pub matcher: WindowMatcher,
+    xid: Option<u64>,
+}
+
+impl WindowTarget {
+    pub fn new(matcher: WindowMatcher) -> Self {
+        Self { matcher, xid: None }
+    }
+
+    pub fn resolve(&mut self, display: *mut xlib::Display) -> Option<u64> {
+        if let Some(xid) = self.xid {
+            if super::window_attributes(display, xid).is_some() {
+                return Some(xid);
+            }
+
+            println!("window {:#x} went away, looking for it again", xid);
+            self.xid = None;
+        }
+
+        if let WindowMatcher::Xid(xid) = self.matcher {
+            super::window_attributes(display, xid)?;
+            self.xid = Some(xid);
+            return self.xid;
+        }
+
+        //  games tend to have a few helper windows matching the same class/pid,
+        //  the big visible one is the one we want
+        let best = list_windows(display)
+            .into_iter()
+            .filter(|w| self.matcher.matches(w))
+            .max_by_key(|w| (w.viewable, w.width as u64 * w.height as u64))?;
+
+        println!(
+            "capturing window {:#x} ({})",
+            best.xid,
+            best.title.as_deref().unwrap_or("untitled")
+        );
+        self.xid = Some(best.xid);
+        self.xid
+    }
+}
+
+/**
+ * walks the whole window tree and returns every window
+ * that has a title, a class or a pid set
+ */
+pub fn list_windows(display: *mut xlib::Display) -> Vec<WindowInfo> {
+    let mut windows = vec![];
+    let atoms = Atoms::new(display);
+
+    let root = unsafe { xlib::XDefaultRootWindow(display) };
+    let mut pending = vec![root];
+
+    while let Some(xid) = pending.pop() {
+        pending.extend(children(display, xid));
+
+        let attr = match super::window_attributes(display, xid) {
+            Some(attr) => attr,
+            None => continue,
+        };
+
+        let title = text_property(display, xid, atoms.net_wm_name)
+            .or_else(|| text_property(display, xid, xlib::XA_WM_NAME));
+        let class = text_property(display, xid, xlib::XA_WM_CLASS)
+            .map(|c| {
+                c.split('\0')
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let pid = cardinal_property(display, xid, atoms.net_wm_pid);
+
+        if title.is_none() && class.is_empty() && pid.is_none() {
+            continue;
+        }
+
+        windows.push(WindowInfo {
+            xid,
+            title,
+            class,
+            pid,
+            width: attr.width as u32,
+            height: attr.height as u32,
+            viewable: attr.map_state == xlib::IsViewable,
+        });
+    }
+
+    windows
+}
+
+struct Atoms {
+    net_wm_name: xlib::Atom,
+    net_wm_pid: xlib::Atom,
+}
+
+impl Atoms {
+    fn new(display: *mut xlib::Display) -> Self {
+        Self {
+            net_wm_name: intern(display, "_NET_WM_NAME"),
+            net_wm_pid: intern(display, "_NET_WM_PID"),
+        }
+    }
+}
+
+fn intern(display: *mut xlib::Display, name: &str) -> xlib::Atom {
+    let name = CString::new(name).unwrap();
+    unsafe { xlib::XInternAtom(display, name.as_ptr(), 0) }
+}
+
+fn children(display: *mut xlib::Display, xid: u64) -> Vec<u64> {
+    let mut root = 0;
+    let mut parent = 0;
+    let mut children: *mut xlib::Window = null_mut();
+    let mut count: c_uint = 0;
+
+    let status = unsafe {
+        xlib::XQueryTree(
+            display,
+            xid,
+            &mut root,
+            &mut parent,
+            &mut children,
+            &mut count,
+        )
+    };
+    if status == 0 || children.is_null() {
+        return vec![];
+    }
+
+    let list = unsafe { std::slice::from_raw_parts(children, count as usize).to_vec() };
+    unsafe {
+        xlib::XFree(children as *mut _);
+    }
+
+    list
+}
+
+/**
+ * raw bytes of a window property, along with its format (8/16/32)
+ */
+fn property(display: *mut xlib::Display, xid: u64, atom: xlib::Atom) -> Option<(Vec<u8>, c_int)> {
+    let mut actual_type: xlib::Atom = 0;
+    let mut format: c_int = 0;
+    let mut items: c_ulong = 0;
+    let mut remaining: c_ulong = 0;
+    let mut data: *mut c_uchar = null_mut();
+
+    let status = unsafe {
+        xlib::XGetWindowProperty(
+            display,
+            xid,
+            atom,
+            0,
+            1024,
+            0,
+            xlib::AnyPropertyType as u64,
+            &mut actual_type,
+            &mut format,
+            &mut items,
+            &mut remaining,
+            &mut data,
+        )
+    };
+    if status != xlib::Success as c_int || data.is_null() {
+        return None;
+    }
+
+    //  32-bit items come back as longs
+    let item_size = match format {
+        8 => 1,
+        16 => std::mem::size_of::<std::os::raw::c_short>(),
+        _ => std::mem::size_of::<c_ulong>(),
+    };
+    let bytes = unsafe { std::slice::from_raw_parts(data, items as usize * item_size).to_vec() };
+    unsafe {
+        xlib::XFree(data as *mut _);
+    }
+
+    if items == 0 {
+        return None;
+    }
+
+    Some((bytes, format))
+}
+
+fn text_property(display: *mut xlib::Display, xid: u64, atom: xlib::Atom) -> Option<String> {
+    match property(display, xid, atom)? {
+        (bytes, 8) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        _ => None,
+    }
+}
+
+fn cardinal_property(display: *mut xlib::Display, xid: u64, atom: xlib::Atom) -> Option<u32> {
+    match property(display, xid, atom)? {
+        (bytes, 32) => {
+            let mut value = [0u8; std::mem::size_of::<c_ulong>()];
+            let len = value.len();
+            value.copy_from_slice(&bytes[..len]);
+            Some(c_ulong::from_ne_bytes(value) as u32)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,4 @@
+pub mod pacing;
+#[cfg(target_os = "linux")]
+pub mod pipeline;
+pub mod queue;
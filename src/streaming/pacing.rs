@@ -0,0 +1,219 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/**
+ * how close we got to the frame rate we were aiming for
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct PacingReport {
+    pub target_fps: u32,
+    pub achieved_fps: f64,
+
+    /**
+     * frame slots we had to drop because we fell behind
+     */
+    pub skipped: u64,
+}
+
+/**
+ * hands out frame slots at a fixed rate against the monotonic clock
+ *
+ * if a frame takes longer than its slot the missed slots are dropped,
+ * we don't try to catch up by bursting frames out
+ */
+pub struct Pacer {
+    fps: u32,
+    interval: Duration,
+    next: Instant,
+
+    window_start: Instant,
+    frames: u64,
+    skipped: u64,
+}
+
+impl Pacer {
+    /**
+     * how often `report` has something to say
+     */
+    const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+    pub fn new(fps: u32) -> Self {
+        let now = Instant::now();
+
+        Self {
+            fps: fps.max(1),
+            interval: Self::interval_for(fps),
+            next: now,
+            window_start: now,
+            frames: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn interval_for(fps: u32) -> Duration {
+        Duration::from_nanos(1_000_000_000 / fps.max(1) as u64)
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    pub fn set_fps(&mut self, fps: u32) {
+        let fps = fps.max(1);
+        if fps == self.fps {
+            return;
+        }
+
+        println!("pacing at {} fps", fps);
+        self.fps = fps;
+        self.interval = Self::interval_for(fps);
+        self.next = Instant::now();
+    }
+
+    /**
+     * sleeps until the next slot is due and returns how many slots
+     * were skipped because the last frame ran late
+     */
+    pub fn wait(&mut self) -> u32 {
+        let now = Instant::now();
+
+        if now < self.next {
+            thread::sleep(self.next - now);
+            self.next += self.interval;
+            return 0;
+        }
+
+        let missed = ((now - self.next).as_nanos() / self.interval.as_nanos()) as u32;
+        self.next += self.interval * (missed + 1);
+        self.skipped += missed as u64;
+
+        missed
+    }
+
+    /**
+     * call once per frame that actually went out
+     */
+    pub fn frame_sent(&mut self) {
+        self.frames += 1;
+    }
+
+    /**
+     * achieved vs target rate, every few seconds
+     */
+    pub fn report(&mut self) -> Option<PacingReport> {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < Self::REPORT_INTERVAL {
+            return None;
+        }
+
+        let report = PacingReport {
+            target_fps: self.fps,
+            achieved_fps: self.frames as f64 / elapsed.as_secs_f64(),
+            skipped: self.skipped,
+        };
+
+        self.window_start = Instant::now();
+        self.frames = 0;
+        self.skipped = 0;
+
+        Some(report)
+    }
+}
+
+/**
+ * spreads datagrams out at a bit over the target bitrate, so a frame
+ * doesn't hit the network as one burst that overflows some router's queue
+ *
+ * a few milliseconds' worth can go out back to back, past that `wait` sleeps
+ */
+pub struct PacketPacer {
+    bytes_per_sec: f64,
+
+    /**
+     * what can still go out right now, negative if we're ahead
+     */
+    budget: f64,
+    last: Instant,
+}
+
+impl PacketPacer {
+    /**
+     * how much faster than the target we send, frames still
+     * need to go out well within a frame interval
+     */
+    const FACTOR: f64 = 2.5;
+    const BURST: Duration = Duration::from_millis(5);
+
+    pub fn new(kbps: u32) -> Self {
+        let mut pacer = Self {
+            bytes_per_sec: 0.0,
+            budget: 0.0,
+            last: Instant::now(),
+        };
+        pacer.set_bitrate(kbps);
+
+        pacer
+    }
+
+    pub fn set_bitrate(&mut self, kbps: u32) {
+        self.bytes_per_sec = kbps.max(1) as f64 * 1000.0 / 8.0 * Self::FACTOR;
+    }
+
+    /**
+     * sleeps until `bytes` may go out
+     */
+    pub fn wait(&mut self, bytes: usize) {
+        let now = Instant::now();
+        let burst = self.bytes_per_sec * Self::BURST.as_secs_f64();
+        self.budget = (self.budget
+            + now.duration_since(self.last).as_secs_f64() * self.bytes_per_sec)
+            .min(burst);
+        self.last = now;
+
+        if self.budget < 0.0 {
+            thread::sleep(Duration::from_secs_f64(-self.budget / self.bytes_per_sec));
+            self.budget = 0.0;
+            self.last = Instant::now();
+        }
+        self.budget -= bytes as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_follow_the_rate() {
+        assert_eq!(Pacer::interval_for(30), Duration::from_nanos(33_333_333));
+        assert_eq!(Pacer::interval_for(1), Duration::from_secs(1));
+        //  no dividing by zero
+        assert_eq!(Pacer::interval_for(0), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn waits_for_the_next_slot() {
+        let mut pacer = Pacer::new(100);
+        assert_eq!(pacer.wait(), 0);
+
+        let started = Instant::now();
+        assert_eq!(pacer.wait(), 0);
+        assert!(started.elapsed() >= Duration::from_millis(5));
+        assert_eq!(pacer.skipped, 0);
+    }
+
+    #[test]
+    fn late_frames_skip_slots() {
+        let mut pacer = Pacer::new(100);
+        pacer.wait();
+
+        //  the next slot is 10ms out, three more go by
+        thread::sleep(Duration::from_millis(45));
+        let missed = pacer.wait();
+        assert!(missed >= 3, "missed {}", missed);
+        assert_eq!(pacer.skipped, missed as u64);
+
+        //  and we're back on the grid instead of bursting to catch up
+        assert!(pacer.next > Instant::now());
+    }
+}
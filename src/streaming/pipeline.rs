@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::codec::{self, CodecId, EncoderSettings, FrameEncoder, Packet};
+use crate::networking::congestion::TargetBitrate;
+use crate::networking::subscribers::{CursorMode, Subscriber};
+use crate::recording::scale::{self, ScaleFilter, Scaler};
+use crate::recording::{self, cursor::Cursor, pixels::Frame};
+
+use super::queue;
+
+/**
+ * handed from the capture thread to the encoders
+ */
+pub struct Captured {
+    /**
+     * increases by one for every captured frame,
+     * the sender uses it to put frames back in order
+     */
+    pub seq: u64,
+    pub frame: Frame,
+    pub cursor: Option<Cursor>,
+
+    /**
+     * who this frame is for, decided when it was captured
+     */
+    pub subscribers: Vec<Subscriber>,
+
+    /**
+     * somebody new is watching, they can't start decoding in the middle
+     */
+    pub keyframe: bool,
+}
+
+/**
+ * one stream we encode, every subscriber gets exactly one of them
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Variant {
+    pub codec: CodecId,
+
+    /**
+     * with the cursor drawn in, for `CursorMode::Blend`
+     */
+    pub blended: bool,
+
+    /**
+     * what the client asked to have frames scaled down to fit
+     */
+    pub size: Option<(u32, u32)>,
+    pub filter: ScaleFilter,
+}
+
+impl Variant {
+    pub fn of(subscriber: &Subscriber) -> Self {
+        Self {
+            codec: subscriber.codec,
+            blended: subscriber.settings.cursor == CursorMode::Blend,
+            size: subscriber.settings.size,
+            filter: subscriber.settings.filter,
+        }
+    }
+
+    /**
+     * what `--record-session` writes, the whole frame without
+     * the cursor, whether or not somebody is watching it
+     */
+    pub fn recorded(codec: CodecId) -> Self {
+        Self {
+            codec,
+            blended: false,
+            size: None,
+            filter: ScaleFilter::Bilinear,
+        }
+    }
+
+    /**
+     * everything that decides what goes into the encoder
+     */
+    fn picture(&self) -> Picture {
+        Picture {
+            blended: self.blended,
+            size: self.size,
+            filter: self.filter,
+        }
+    }
+}
+
+/**
+ * one frame as it's handed to the encoders, variants
+ * that only differ in codec share it
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct Picture {
+    blended: bool,
+    size: Option<(u32, u32)>,
+    filter: ScaleFilter,
+}
+
+/**
+ * handed from the encoders to the sender
+ */
+pub struct Encoded {
+    pub seq: u64,
+
+    /**
+     * one packet for each variant somebody in `subscribers` wants
+     */
+    pub packets: HashMap<Variant, Packet>,
+    pub subscribers: Vec<Subscriber>,
+}
+
+/**
+ * one of the encoder pool's threads, runs until the capture thread goes away
+ *
+ * `recorded` is encoded for every frame on top of what subscribers want
+ */
+pub fn encode_frames(
+    settings: EncoderSettings,
+    recorded: Option<Variant>,
+    target: TargetBitrate,
+    captured: queue::Receiver<Captured>,
+    encoded: queue::Sender<Encoded>,
+) {
+    let mut encoders = Encoders {
+        settings,
+        recorded,
+        encoders: HashMap::new(),
+        scalers: HashMap::new(),
+        used: HashMap::new(),
+    };
+
+    for c in captured {
+        let kbps = target.get();
+        if kbps != encoders.settings.bitrate_kbps {
+            encoders.set_bitrate(kbps);
+        }
+        if c.keyframe {
+            encoders.force_keyframe();
+        }
+        let inter = c.subscribers.iter().any(|s| !s.codec.is_intra_only());
+
+        //  a packet that never made it out breaks every frame after it
+        //  that refers back to it, start over from a keyframe
+        if encoded.push(encoders.encode(c)) && inter {
+            encoders.force_keyframe();
+        }
+    }
+}
+
+/**
+ * how long an encoder outlives the last frame it had somebody for,
+ * a subscriber at a lower rate than ours skips most frames
+ */
+const IDLE_GRACE: Duration = Duration::from_secs(5);
+
+/**
+ * one encoder per variant, they're separate streams as far as the codec cares
+ */
+struct Encoders {
+    settings: EncoderSettings,
+    recorded: Option<Variant>,
+    encoders: HashMap<Variant, Box<dyn FrameEncoder>>,
+    scalers: HashMap<Picture, Scaler>,
+
+    /**
+     * when each variant last had somebody to encode for
+     */
+    used: HashMap<Variant, Instant>,
+}
+
+impl Encoders {
+    fn force_keyframe(&mut self) {
+        for encoder in self.encoders.values_mut() {
+            encoder.force_keyframe();
+        }
+    }
+
+    /**
+     * encoders made from here on start out at `kbps` too
+     */
+    fn set_bitrate(&mut self, kbps: u32) {
+        self.settings.bitrate_kbps = kbps;
+        for encoder in self.encoders.values_mut() {
+            encoder.set_bitrate(kbps);
+        }
+    }
+
+    fn encode(&mut self, captured: Captured) -> Encoded {
+        let mut variants: Vec<Variant> = vec![];
+        let watched = captured.subscribers.iter().map(Variant::of);
+        for variant in watched.chain(self.recorded) {
+            if !variants.contains(&variant) {
+                variants.push(variant);
+            }
+        }
+
+        //  nobody's watched these in a while, a fresh encoder starts
+        //  with a keyframe if they're wanted again
+        let now = Instant::now();
+        for variant in &variants {
+            self.used.insert(*variant, now);
+        }
+        self.used
+            .retain(|_, at| now.duration_since(*at) < IDLE_GRACE);
+        let used = &self.used;
+        self.encoders.retain(|v, _| used.contains_key(v));
+        self.scalers
+            .retain(|p, _| used.keys().any(|v| v.picture() == *p));
+
+        //  the cursor goes in before scaling so it shrinks along with the frame
+        let blended = if variants.iter().any(|v| v.blended) {
+            let mut frame = captured.frame.clone();
+            if let Some(c) = captured.cursor.as_ref() {
+                recording::cursor::blend(&mut frame, c);
+            }
+            Some(frame)
+        } else {
+            None
+        };
+
+        let mut pictures: HashMap<Picture, Frame> = HashMap::new();
+        for variant in &variants {
+            let picture = variant.picture();
+            let (max_width, max_height) = match picture.size {
+                Some(size) if !pictures.contains_key(&picture) => size,
+                _ => continue,
+            };
+
+            let frame = match (picture.blended, blended.as_ref()) {
+                (true, Some(blended)) => blended,
+                _ => &captured.frame,
+            };
+            let (width, height) = scale::fit(frame.width, frame.height, max_width, max_height);
+            let scaled = self
+                .scalers
+                .entry(picture)
+                .or_insert_with(|| Scaler::new(picture.filter))
+                .scale(frame, width, height);
+            pictures.insert(picture, scaled);
+        }
+
+        let settings = &self.settings;
+        let mut packets = HashMap::new();
+        for variant in variants {
+            let frame = match (pictures.get(&variant.picture()), blended.as_ref()) {
+                (Some(scaled), _) => scaled,
+                (None, Some(blended)) if variant.blended => blended,
+                _ => &captured.frame,
+            };
+
+            let encoder = self
+                .encoders
+                .entry(variant)
+                .or_insert_with(|| codec::encoder(variant.codec, settings));
+            packets.insert(variant, encoder.encode(frame));
+        }
+
+        Encoded {
+            seq: captured.seq,
+            packets,
+            subscribers: captured.subscribers,
+        }
+    }
+}
+
+/**
+ * how many encoder threads to run, one per core but leave some
+ * room for capturing and sending
+ *
+ * inter-frame codecs need every frame in order, so they get just the one,
+ * and so do slices, they already spread each frame over rayon's pool
+ */
+pub fn encoder_count(codecs: &[CodecId]) -> usize {
+    if codecs
+        .iter()
+        .any(|c| !c.is_intra_only() || *c == CodecId::Slices)
+    {
+        return 1;
+    }
+
+    std::thread::available_parallelism()
+        .map(|n| n.get().saturating_sub(2))
+        .unwrap_or(1)
+        .max(1)
+}
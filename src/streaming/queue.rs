@@ -0,0 +1,163 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crossbeam_channel::TrySendError;
+
+pub use crossbeam_channel::Receiver;
+
+/**
+ * the sending half of a bounded queue that never blocks,
+ * when it's full the oldest item is thrown out to make room
+ *
+ * a stale frame is worth less than a fresh one, so a slow stage
+ * makes the stages before it skip frames instead of piling up latency
+ */
+pub struct Sender<T> {
+    tx: crossbeam_channel::Sender<T>,
+
+    /**
+     * our own handle to the queue so we can pop the oldest item
+     */
+    rx: crossbeam_channel::Receiver<T>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = crossbeam_channel::bounded(capacity.max(1));
+
+    (
+        Sender {
+            tx,
+            rx: rx.clone(),
+            dropped: Arc::new(AtomicU64::new(0)),
+        },
+        rx,
+    )
+}
+
+impl<T> Sender<T> {
+    /**
+     * true if something older had to go to make room
+     */
+    pub fn push(&self, item: T) -> bool {
+        !self.push_evicting(item).is_empty()
+    }
+
+    /**
+     * like `push`, but hands back whatever had to go to make room
+     */
+    pub fn push_evicting(&self, mut item: T) -> Vec<T> {
+        let mut dropped = vec![];
+
+        loop {
+            match self.tx.try_send(item) {
+                Ok(()) => return dropped,
+                Err(TrySendError::Full(i)) => {
+                    //  somebody else might have popped it in the meantime, just retry
+                    if let Ok(old) = self.rx.try_recv() {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        dropped.push(old);
+                    }
+                    item = i;
+                }
+                //  can't happen, we're holding a receiver ourselves
+                Err(TrySendError::Disconnected(_)) => return dropped,
+            }
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+/**
+ * lets whoever reports on the pipeline see how a queue is doing
+ * without holding on to its sending end
+ */
+pub struct QueueStats {
+    dropped: Arc<AtomicU64>,
+}
+
+impl QueueStats {
+    /**
+     * how many items were thrown out since the last call
+     */
+    pub fn take_dropped(&self) -> u64 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_items() {
+        let (tx, rx) = bounded(2);
+        let stats = tx.stats();
+
+        assert!(!tx.push(1));
+        assert!(!tx.push(2));
+        assert!(tx.push(3));
+        assert!(tx.push(4));
+
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(stats.take_dropped(), 2);
+        assert_eq!(stats.take_dropped(), 0);
+    }
+
+    #[test]
+    fn evicted_items_are_handed_back() {
+        let (tx, rx) = bounded(2);
+
+        assert!(tx.push_evicting(1).is_empty());
+        assert!(tx.push_evicting(2).is_empty());
+        assert_eq!(tx.push_evicting(3), vec![1]);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn room_frees_up_as_items_are_taken() {
+        let (tx, rx) = bounded(1);
+        let stats = tx.stats();
+
+        for i in 0..3 {
+            assert!(!tx.push(i));
+            assert_eq!(rx.recv(), Ok(i));
+        }
+        assert_eq!(stats.take_dropped(), 0);
+    }
+
+    #[test]
+    fn capacity_is_at_least_one() {
+        let (tx, rx) = bounded(0);
+        assert!(!tx.push("a"));
+        assert!(tx.push("b"));
+        assert_eq!(rx.try_recv(), Ok("b"));
+    }
+
+    #[test]
+    fn receivers_stop_once_every_sender_is_gone() {
+        let (tx, rx) = bounded(2);
+        let other = tx.clone();
+        tx.push(1);
+        drop(tx);
+        other.push(2);
+        drop(other);
+
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![1, 2]);
+    }
+}